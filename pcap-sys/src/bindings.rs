@@ -13,6 +13,8 @@ pub const PCAP_ERRBUF_SIZE: u32 = 256;
 pub const PCAP_IF_LOOPBACK: u32 = 1;
 pub const PCAP_IF_UP: u32 = 2;
 pub const PCAP_IF_RUNNING: u32 = 4;
+pub const PCAP_IF_WIRELESS: u32 = 8;
+pub const PCAP_IF_CONNECTION_STATUS: u32 = 48;
 pub const PCAP_ERROR: i32 = -1;
 pub const PCAP_ERROR_BREAK: i32 = -2;
 pub const PCAP_ERROR_NOT_ACTIVATED: i32 = -3;
@@ -36,6 +38,10 @@ pub const PCAP_TSTAMP_ADAPTER: u32 = 3;
 pub const PCAP_TSTAMP_ADAPTER_UNSYNCED: u32 = 4;
 pub const PCAP_TSTAMP_PRECISION_MICRO: u32 = 0;
 pub const PCAP_TSTAMP_PRECISION_NANO: u32 = 1;
+pub const PCAP_BUF_SIZE: u32 = 1024;
+pub const PCAP_SRC_FILE: u32 = 2;
+pub const PCAP_SRC_IFLOCAL: u32 = 3;
+pub const PCAP_SRC_IFREMOTE: u32 = 4;
 pub type u_char = libc::c_uchar;
 pub type u_short = libc::c_ushort;
 pub type u_int = libc::c_uint;
@@ -415,3 +421,23 @@ extern "C" {
 extern "C" {
     pub fn pcap_free_selectable_fd_list(arg1: *mut libc::c_int);
 }
+extern "C" {
+    pub fn pcap_createsrcstr(
+        source: *mut libc::c_char,
+        type_: libc::c_int,
+        host: *const libc::c_char,
+        port: *const libc::c_char,
+        name: *const libc::c_char,
+        errbuf: *mut libc::c_char,
+    ) -> libc::c_int;
+}
+extern "C" {
+    pub fn pcap_parsesrcstr(
+        source: *const libc::c_char,
+        type_: *mut libc::c_int,
+        host: *mut libc::c_char,
+        port: *mut libc::c_char,
+        name: *mut libc::c_char,
+        errbuf: *mut libc::c_char,
+    ) -> libc::c_int;
+}
@@ -0,0 +1,104 @@
+//! Verifying IPv4, TCP and UDP checksums of captured packets
+//!
+//! Outgoing packets captured on a host with checksum offload carry whatever
+//! the stack left in the checksum field, because the NIC only fills it in
+//! after the capture point. Expect those to fail verification.
+//!
+//! The TCP and UDP checks need the whole segment, so they return `None` for
+//! packets that were truncated by the snaplen or aren't of that protocol.
+use ip::{IpPacket, Ipv4Packet, PROTO_TCP, PROTO_UDP};
+
+/// One's complement sum of `data` as big-endian 16-bit words, not yet folded
+pub(crate) fn sum_words(data: &[u8]) -> u64 {
+    let mut chunks = data.chunks_exact(2);
+    let mut sum = chunks
+        .by_ref()
+        .map(|word| u64::from(u16::from_be_bytes([word[0], word[1]])))
+        .sum::<u64>();
+    if let [last] = chunks.remainder() {
+        sum += u64::from(*last) << 8;
+    }
+    sum
+}
+
+pub(crate) fn fold(mut sum: u64) -> u16 {
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum as u16
+}
+
+fn pseudo_header_sum(packet: &IpPacket, protocol: u8, len: usize) -> u64 {
+    match packet {
+        IpPacket::V4(ip) => {
+            sum_words(&ip.src().octets())
+                + sum_words(&ip.dst().octets())
+                + u64::from(protocol)
+                + len as u64
+        }
+        IpPacket::V6(ip) => {
+            sum_words(&ip.src().octets())
+                + sum_words(&ip.dst().octets())
+                + sum_words(&(len as u32).to_be_bytes())
+                + u64::from(protocol)
+        }
+    }
+}
+
+fn verify_transport(packet: &IpPacket, protocol: u8) -> Option<bool> {
+    if packet.protocol() != protocol || packet.is_truncated() {
+        return None;
+    }
+    let segment = packet.payload();
+    let sum = pseudo_header_sum(packet, protocol, segment.len()) + sum_words(segment);
+    Some(fold(sum) == 0xffff)
+}
+
+/// Whether the IPv4 header checksum is correct
+pub fn verify_ipv4_checksum(packet: &Ipv4Packet) -> bool {
+    fold(sum_words(packet.header())) == 0xffff
+}
+
+/// Whether the TCP checksum, covering the pseudo-header, is correct
+pub fn verify_tcp_checksum(packet: &IpPacket) -> Option<bool> {
+    verify_transport(packet, PROTO_TCP)
+}
+
+/// Whether the UDP checksum, covering the pseudo-header, is correct. Over
+/// IPv4 a zero checksum means the sender didn't compute one and is accepted.
+pub fn verify_udp_checksum(packet: &IpPacket) -> Option<bool> {
+    if let IpPacket::V4(_) = packet {
+        let segment = packet.payload();
+        if packet.protocol() == PROTO_UDP && segment.len() >= 8 && segment[6..8] == [0, 0] {
+            return Some(true);
+        }
+    }
+    verify_transport(packet, PROTO_UDP)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_ipv4_and_udp() {
+        let mut packet = [
+            0x45, 0x00, 0x00, 0x1f, 0x12, 0x34, 0x00, 0x00, 0x40, 0x11, 0xe5, 0x46, 0xc0, 0xa8,
+            0x01, 0x02, 0xc0, 0xa8, 0x01, 0x01, 0x14, 0xe9, 0x00, 0x35, 0x00, 0x0b, 0xdd, 0xfc,
+            0x68, 0x69, 0x21,
+        ];
+        {
+            let ip = IpPacket::parse(&packet).unwrap();
+            match ip {
+                IpPacket::V4(v4) => assert!(verify_ipv4_checksum(&v4)),
+                IpPacket::V6(_) => panic!("parsed as IPv6"),
+            }
+            assert_eq!(verify_udp_checksum(&ip), Some(true));
+            assert_eq!(verify_tcp_checksum(&ip), None);
+            assert_eq!(verify_udp_checksum(&IpPacket::parse(&packet[..30]).unwrap()), None);
+        }
+
+        packet[30] ^= 0xff;
+        assert_eq!(verify_udp_checksum(&IpPacket::parse(&packet).unwrap()), Some(false));
+    }
+}
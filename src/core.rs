@@ -5,37 +5,50 @@ use bitflags::bitflags;
 #[cfg(unix)]
 use libc::{AF_INET, AF_INET6, sockaddr_in, sockaddr_in6};
 use pcap_sys as ffi;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::ffi::CStr;
 use std::ffi::CString;
-use std::ffi::FromBytesWithNulError;
+use std::fmt::{self, Write};
+use std::fs::File;
+use std::io::{self, Read};
+#[cfg(unix)]
+use std::os::unix::io::IntoRawFd;
 use std::mem::MaybeUninit;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+use std::rc::Rc;
 use std::slice;
 #[cfg(feature="breakable")]
 use std::sync::Arc;
+#[cfg(feature="breakable")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
 use std::time::Duration;
 use std::time::SystemTime;
+use link;
+use pcapng;
+use types::ERROR_UNSUPPORTED_FORMAT;
+pub use types::{
+    Address, DataLinkType, Error, ErrorKind, LinkHeaderKind, Packet, PacketHeader, Stats, TimeStamp,
+    TstampPrecision, MAX_SNAPLEN,
+};
 #[cfg(windows)]
 use winapi::shared::ws2def::{AF_INET, AF_INET6, SOCKADDR_IN as sockaddr_in};
 #[cfg(windows)]
 use winapi::shared::ws2ipdef::SOCKADDR_IN6_LH as sockaddr_in6;
 
-#[derive(Debug)]
-pub struct Address {
-    pub address: Option<SocketAddr>,
-    pub netmask: Option<SocketAddr>,
-    pub broadcast: Option<SocketAddr>,
-    pub destination: Option<SocketAddr>,
-}
-
 #[cfg(unix)]
 fn socketaddr_from_sockaddr(addr: &mut ffi::sockaddr) -> Option<SocketAddr> {
     match addr.sa_family as i32 {
         AF_INET => {
             let addr = unsafe { *(addr as *mut ffi::sockaddr as *mut sockaddr_in) };
             let raw_addr = addr.sin_addr.s_addr;
-            let port = addr.sin_port;
-            let ipv4_address = Ipv4Addr::from(raw_addr);
+            let port = u16::from_be(addr.sin_port);
+            let ipv4_address = Ipv4Addr::from(u32::from_be(raw_addr));
             let sock_address = SocketAddrV4::new(ipv4_address, port);
 
             Some(SocketAddr::V4(sock_address))
@@ -43,7 +56,7 @@ fn socketaddr_from_sockaddr(addr: &mut ffi::sockaddr) -> Option<SocketAddr> {
         AF_INET6 => {
             let addr = unsafe { *(addr as *mut ffi::sockaddr as *mut sockaddr_in6) };
             let raw_addr = addr.sin6_addr.s6_addr;
-            let port = addr.sin6_port;
+            let port = u16::from_be(addr.sin6_port);
             let ipv6_address: Ipv6Addr = Ipv6Addr::from(raw_addr);
             let scope_id = addr.sin6_scope_id;
             let flowinfo = addr.sin6_flowinfo;
@@ -61,8 +74,8 @@ fn socketaddr_from_sockaddr(addr: &mut ffi::sockaddr) -> Option<SocketAddr> {
         AF_INET => {
             let addr = unsafe { *(addr as *mut ffi::sockaddr as *mut sockaddr_in) };
             let raw_addr = unsafe { addr.sin_addr.S_un.S_addr() };
-            let port = addr.sin_port;
-            let ipv4_address = Ipv4Addr::from(*raw_addr);
+            let port = u16::from_be(addr.sin_port);
+            let ipv4_address = Ipv4Addr::from(u32::from_be(*raw_addr));
             let sock_address = SocketAddrV4::new(ipv4_address, port);
 
             Some(SocketAddr::V4(sock_address))
@@ -70,7 +83,7 @@ fn socketaddr_from_sockaddr(addr: &mut ffi::sockaddr) -> Option<SocketAddr> {
         AF_INET6 => {
             let addr = unsafe { *(addr as *mut ffi::sockaddr as *mut sockaddr_in6) };
             let raw_addr = unsafe { addr.sin6_addr.u.Byte() };
-            let port = addr.sin6_port;
+            let port = u16::from_be(addr.sin6_port);
             let ipv6_address: Ipv6Addr = Ipv6Addr::from(*raw_addr);
             let scope_id = unsafe { addr.u.sin6_scope_id() };
             let flowinfo = addr.sin6_flowinfo;
@@ -112,18 +125,43 @@ bitflags! {
         const PCAP_IF_LOOPBACK = ffi::PCAP_IF_LOOPBACK;
         const PCAP_IF_UP = ffi::PCAP_IF_UP;
         const PCAP_IF_RUNNING = ffi::PCAP_IF_RUNNING;
+        const PCAP_IF_WIRELESS = ffi::PCAP_IF_WIRELESS;
+        const PCAP_IF_CONNECTION_STATUS = ffi::PCAP_IF_CONNECTION_STATUS;
     }
 }
 
+/// Where a `NetworkInterface` was found
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum InterfaceSource {
+    /// listed by `pcap_findalldevs`, with its addresses and flags
+    Libpcap,
+    /// only known to the operating system; has no addresses and only the
+    /// up, running and loopback flags
+    System,
+}
+
 #[derive(Debug)]
 pub struct NetworkInterface {
     name: String,
     description: Option<String>,
     addresses: Vec<Address>,
     flags: IfFlags,
+    source: InterfaceSource,
 }
 
 impl NetworkInterface {
+    /// An interface that didn't come from libpcap, e.g. for tests or for
+    /// listing interfaces from another source. It is marked
+    /// `InterfaceSource::System`.
+    pub fn new(name: &str, description: Option<&str>, addresses: Vec<Address>, flags: IfFlags) -> NetworkInterface {
+        NetworkInterface {
+            name: name.to_owned(),
+            description: description.map(str::to_owned),
+            addresses,
+            flags,
+            source: InterfaceSource::System,
+        }
+    }
 
     pub fn name(&self) -> &str {
         &self.name
@@ -133,10 +171,45 @@ impl NetworkInterface {
         self.description.as_ref().map(AsRef::as_ref)
     }
 
+    /// Something to show a person: the description, e.g. the adapter model
+    /// on Windows, or the name when there is none, as is usual on Linux
+    pub fn friendly_name(&self) -> &str {
+        match self.description() {
+            Some(description) if !description.is_empty() => description,
+            _ => &self.name,
+        }
+    }
+
     pub fn addresses(&self) -> &[Address] {
         &self.addresses
     }
 
+    pub fn source(&self) -> InterfaceSource {
+        self.source
+    }
+
+    pub fn ipv4_addresses(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.addresses.iter().filter_map(|address| match address.address {
+            Some(SocketAddr::V4(addr)) => Some(*addr.ip()),
+            _ => None,
+        })
+    }
+
+    pub fn ipv6_addresses(&self) -> impl Iterator<Item = Ipv6Addr> + '_ {
+        self.addresses.iter().filter_map(|address| match address.address {
+            Some(SocketAddr::V6(addr)) => Some(*addr.ip()),
+            _ => None,
+        })
+    }
+
+    pub fn has_ipv4(&self) -> bool {
+        self.ipv4_addresses().next().is_some()
+    }
+
+    pub fn has_ipv6(&self) -> bool {
+        self.ipv6_addresses().next().is_some()
+    }
+
     pub fn is_loopback(&self) -> bool {
         self.flags.contains(IfFlags::PCAP_IF_LOOPBACK)
     }
@@ -148,6 +221,80 @@ impl NetworkInterface {
     pub fn is_up(&self) -> bool {
         self.flags.contains(IfFlags::PCAP_IF_UP)
     }
+
+    /// Only reported by libpcap 1.9 and later; always false on older versions
+    pub fn is_wireless(&self) -> bool {
+        self.flags.contains(IfFlags::PCAP_IF_WIRELESS)
+    }
+
+    /// Whether the interface can be put into monitor mode.
+    ///
+    /// Loopback interfaces are rejected outright. If libpcap reported a
+    /// connection status for the interface then it is new enough to also
+    /// report `PCAP_IF_WIRELESS`, so wired interfaces are rejected without
+    /// opening anything. Only the remaining candidates pay for a
+    /// `pcap_create`/`pcap_can_set_rfmon`/`pcap_close` round trip, which never
+    /// activates the handle and so doesn't disturb the interface.
+    pub fn supports_monitor_mode(&self) -> Result<bool, Error> {
+        if self.is_loopback() {
+            return Ok(false);
+        }
+        if !self.is_wireless() && self.flags.intersects(IfFlags::PCAP_IF_CONNECTION_STATUS) {
+            return Ok(false);
+        }
+        create(&self.name)?.can_set_rfmon()
+    }
+}
+
+/// The `IfFlags` equivalent of a `/sys/class/net/<name>/flags` value (the
+/// kernel's `IFF_*` bits, in hex) and `operstate`
+#[cfg(target_os = "linux")]
+fn flags_from_sysfs(flags: &str, operstate: &str) -> IfFlags {
+    const IFF_UP: u32 = 0x1;
+    const IFF_LOOPBACK: u32 = 0x8;
+
+    let raw = u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).unwrap_or(0);
+    let mut flags = IfFlags::empty();
+    flags.set(IfFlags::PCAP_IF_UP, raw & IFF_UP != 0);
+    flags.set(IfFlags::PCAP_IF_LOOPBACK, raw & IFF_LOOPBACK != 0);
+    flags.set(IfFlags::PCAP_IF_RUNNING, operstate.trim() == "up");
+    flags
+}
+
+/// Whether a `/sys/class/net/<name>/flags` value has `IFF_PROMISC` set
+#[cfg(target_os = "linux")]
+fn promisc_from_sysfs(flags: &str) -> bool {
+    const IFF_PROMISC: u32 = 0x100;
+    u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).unwrap_or(0) & IFF_PROMISC != 0
+}
+
+/// `find_all_devs`, plus the interfaces in `/sys/class/net` that libpcap
+/// left out (it skips some virtual and down interfaces). Those are marked
+/// `InterfaceSource::System`; where both know an interface, libpcap's entry
+/// is kept. If `/sys` can't be read, this is just `find_all_devs`.
+#[cfg(target_os = "linux")]
+pub fn find_all_devs_with_hidden() -> Result<Vec<NetworkInterface>, Error> {
+    use std::fs;
+
+    let mut interfaces: Vec<NetworkInterface> = find_all_devs()?.collect();
+    let entries = match fs::read_dir("/sys/class/net") {
+        Ok(entries) => entries,
+        Err(_) => return Ok(interfaces),
+    };
+    let mut hidden: Vec<NetworkInterface> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if interfaces.iter().any(|interface| interface.name == name) {
+                return None;
+            }
+            let read = |file: &str| fs::read_to_string(entry.path().join(file)).unwrap_or_default();
+            Some(NetworkInterface::new(&name, None, vec![], flags_from_sysfs(&read("flags"), &read("operstate"))))
+        })
+        .collect();
+    hidden.sort_by(|a, b| a.name.cmp(&b.name));
+    interfaces.extend(hidden);
+    Ok(interfaces)
 }
 
 /// Holds the results of `find_all_devs`.
@@ -188,6 +335,7 @@ impl From<ffi::pcap_if> for NetworkInterface {
                     .map(|desc| CStr::from_ptr(desc).to_string_lossy().into_owned()),
                 addresses,
                 flags: IfFlags::from_bits_truncate(interface.flags),
+                source: InterfaceSource::Libpcap,
             }
         }
     }
@@ -206,23 +354,6 @@ impl Iterator for NetworkInterfaceIterator {
     }
 }
 
-#[derive(Debug)]
-pub struct Error {
-    message: Option<String>,
-    code: i32,
-}
-
-impl std::error::Error for Error {}
-impl std::fmt::Display for Error {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "pcap error {}", self.code)?;
-        if let Some(message) = &self.message {
-            write!(f, ": {}", message)?;
-        }
-        Ok(())
-    }
-}
-
 /// A `PCAP_ERRBUF_SIZE`-byte buffer for errors to be written to by libpcap
 /// The buffer is passed to libpcap functions as a `*mut libc::c_char`
 struct ErrBuf {
@@ -240,19 +371,26 @@ impl ErrBuf {
         self.buf.as_mut_ptr()
     }
 
-    fn read(&mut self) -> Result<String, FromBytesWithNulError> {
-        let buf = unsafe { &*(&mut self.buf as *mut [i8] as *mut [u8]) };
-        CStr::from_bytes_with_nul(buf).map(|cstr| cstr.to_string_lossy().into_owned())
+    /// The message up to the first nul, if libpcap wrote one
+    fn read(&self) -> Option<String> {
+        let buf = unsafe { &*(&self.buf as *const [i8] as *const [u8]) };
+        CStr::from_bytes_until_nul(buf).ok().and_then(error_message)
+    }
+}
+
+/// libpcap's messages aren't necessarily UTF-8 (they can be localized on
+/// Windows), so invalid bytes are replaced rather than failing
+fn error_message(msg: &CStr) -> Option<String> {
+    match msg.to_string_lossy() {
+        msg if msg.is_empty() => None,
+        msg => Some(msg.into_owned()),
     }
 }
 
 impl Error {
-    fn new(mut err_buf: ErrBuf, err_code: i32) -> Error {
+    fn new(err_buf: ErrBuf, err_code: i32) -> Error {
         Error {
-            message: match err_buf.read() {
-                Ok(msg) => Some(msg),
-                Err(_) => None,
-            },
+            message: err_buf.read(),
             code: err_code,
         }
     }
@@ -261,8 +399,7 @@ impl Error {
         let message = unsafe {
             let ptr = ffi::pcap_geterr(handle);
             if !ptr.is_null() {
-                let msg = CStr::from_ptr(ptr);
-                Some(msg.to_str().unwrap().to_string())
+                error_message(CStr::from_ptr(ptr))
             } else {
                 None
             }
@@ -279,6 +416,71 @@ impl Error {
     }
 }
 
+/// const char *pcap_lib_version(void)
+pub fn lib_version() -> String {
+    unsafe { CStr::from_ptr(ffi::pcap_lib_version()) }.to_string_lossy().into_owned()
+}
+
+/// int pcap_datalink_name_to_val(const char *name)
+///
+/// The link type called `name`, the `DLT_` constant without its prefix as
+/// in "EN10MB" or "LINUX_SLL"; case doesn't matter.
+pub fn datalink_name_to_val(name: &str) -> Result<DataLinkType, Error> {
+    let unknown = || Error::with_message(ffi::PCAP_ERROR, &format!("unknown link type name {:?}", name));
+    let c_name = CString::new(name).map_err(|_| unknown())?;
+    match unsafe { ffi::pcap_datalink_name_to_val(c_name.as_ptr()) } {
+        -1 => Err(unknown()),
+        dlt => Ok(DataLinkType::from_raw(dlt)),
+    }
+}
+
+/// The "X.Y" after "libpcap version " in a `lib_version` string
+fn parse_libpcap_version(version: &str) -> Option<(u32, u32)> {
+    const PREFIX: &str = "libpcap version ";
+    let rest = &version[version.find(PREFIX)? + PREFIX.len()..];
+    let mut parts = rest.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// What the libpcap this process is linked against can do, as found by
+/// `capabilities`
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct Capabilities {
+    /// (major, minor) of the underlying libpcap, if `lib_version` says
+    pub version: Option<(u32, u32)>,
+    /// nanosecond-precision timestamps, probed with a dead handle
+    pub nanosecond_timestamps: bool,
+    /// `list_tstamp_types` and choosing a timestamp type, libpcap 1.2+
+    pub tstamp_types: bool,
+    /// immediate mode, libpcap 1.5+
+    pub immediate_mode: bool,
+    /// remote capture (rpcap). Guessed from the version string: WinPcap and
+    /// Npcap are always built with it, other builds only if they say so.
+    pub remote_capture: bool,
+}
+
+/// Probe the linked libpcap for optional features. Nothing here opens a
+/// device, so it needs no privileges.
+pub fn capabilities() -> Capabilities {
+    let version_string = lib_version();
+    let version = parse_libpcap_version(&version_string);
+    let at_least = |wanted: (u32, u32)| matches!(version, Some(version) if version >= wanted);
+    let nanosecond_timestamps = open_dead_with_tstamp_precision(1, 65535, TstampPrecision::Nano)
+        .map(|handle| handle.tstamp_precision() == TstampPrecision::Nano)
+        .unwrap_or(false);
+    Capabilities {
+        version,
+        nanosecond_timestamps,
+        tstamp_types: at_least((1, 2)),
+        immediate_mode: at_least((1, 5)),
+        remote_capture: version_string.contains("WinPcap")
+            || version_string.contains("Npcap")
+            || version_string.contains("remote"),
+    }
+}
+
 pub fn find_all_devs() -> Result<NetworkInterfaceIterator, Error> {
     let mut all_devs_buf: MaybeUninit<*mut ffi::pcap_if> = MaybeUninit::uninit();
     let mut err_buf = ErrBuf::new();
@@ -294,16 +496,259 @@ pub fn find_all_devs() -> Result<NetworkInterfaceIterator, Error> {
     }
 }
 
+/// The interfaces that can capture with link type `dlt`, for offering only
+/// devices a given parser understands.
+///
+/// libpcap only lists an interface's link types once it is activated, so
+/// each interface is opened once, non-promiscuously and with a small
+/// snaplen, and closed again straight away; interfaces listed as not up are
+/// skipped without opening. Interfaces that fail to open are left out,
+/// except for `ErrorKind::PermDenied`, which is returned because it means
+/// every interface would be left out.
+pub fn find_devs_supporting<D: Into<DataLinkType>>(dlt: D) -> Result<Vec<NetworkInterface>, Error> {
+    let dlt = dlt.into().as_raw();
+    let mut matching = vec![];
+    for interface in find_all_devs()? {
+        if !interface.is_up() {
+            continue;
+        }
+        let probe = create(interface.name()).and_then(|mut handle| {
+            handle.set_snaplen(64)?.set_promisc(false)?;
+            handle.activate()?;
+            handle.list_datalinks()
+        });
+        match probe {
+            Ok(dlts) if dlts.contains(&dlt) => matching.push(interface),
+            Ok(_) => {}
+            Err(err) if err.kind() == ErrorKind::PermDenied => return Err(err),
+            Err(err) => debug!("not probing {}: {}", interface.name(), err),
+        }
+    }
+    Ok(matching)
+}
+
+/// The block size of the TPACKET_V3 ring libpcap maps on Linux: the
+/// smallest power-of-two multiple of the page size that holds one 256 KiB
+/// frame, normally 256 KiB.
+///
+/// libpcap sets up the ring itself during activation and the kernel doesn't
+/// allow changing it once mapped, so the block size can't be chosen; only
+/// the number of blocks can, see `Handle::set_ring_block_count`.
+#[cfg(target_os = "linux")]
+pub fn ring_block_size() -> usize {
+    const FRAME_SIZE: usize = MAX_SNAPLEN as usize;
+    let mut block_size = match unsafe { libc::sysconf(libc::_SC_PAGESIZE) } {
+        page_size if page_size > 0 => page_size as usize,
+        _ => 4096,
+    };
+    while block_size < FRAME_SIZE {
+        block_size <<= 1;
+    }
+    block_size
+}
+
+/// The largest capture buffer the system allows, to pick a value for
+/// `Handle::set_buffer_size` without trial and error: `net.core.rmem_max`
+/// on Linux, the BPF buffer limit (`net.bpf.maxbufsize`,
+/// `debug.bpf_maxbufsize` on macOS) on FreeBSD and macOS. `None` where the
+/// limit can't be read or isn't known.
+///
+/// On Linux this caps the socket buffer used without a memory-mapped ring;
+/// the TPACKET ring libpcap normally sets up is sized from the buffer size
+/// without reference to it.
+#[cfg(target_os = "linux")]
+pub fn max_buffer_size() -> Option<usize> {
+    parse_sysctl(&std::fs::read_to_string("/proc/sys/net/core/rmem_max").ok()?)
+}
+
+#[cfg(any(target_os = "macos", target_os = "freebsd"))]
+pub fn max_buffer_size() -> Option<usize> {
+    #[cfg(target_os = "macos")]
+    const NAME: &[u8] = b"debug.bpf_maxbufsize\0";
+    #[cfg(target_os = "freebsd")]
+    const NAME: &[u8] = b"net.bpf.maxbufsize\0";
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>();
+    let rc = unsafe {
+        libc::sysctlbyname(
+            NAME.as_ptr() as *const libc::c_char,
+            &mut value as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+            ptr::null_mut(),
+            0,
+        )
+    };
+    if rc == 0 && value > 0 { Some(value as usize) } else { None }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "freebsd")))]
+pub fn max_buffer_size() -> Option<usize> {
+    None
+}
+
+/// Pin the calling thread to CPU `cpu` with `sched_setaffinity`, e.g. the
+/// core handling the NIC's interrupts. The crate doesn't spawn capture
+/// threads itself; call this at the start of the thread that will run
+/// `loop_` or `dispatch`, before the first packet.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpu: usize) -> Result<(), Error> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(Error::with_message(ffi::PCAP_ERROR, &format!("CPU {} is out of range", cpu)));
+    }
+    let mut set = unsafe { MaybeUninit::<libc::cpu_set_t>::zeroed().assume_init() };
+    unsafe { libc::CPU_SET(cpu, &mut set) };
+    if unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) } != 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn parse_sysctl(value: &str) -> Option<usize> {
+    value.trim().parse().ok().filter(|&value| value > 0)
+}
+
 /// when break_loop is enabled, we need to be able to extend the lifetime of the
 /// device to that of the "breaker" that is passed elsewhere, in this case, the
 /// responsibility for closing the device is defered to a reference counted
 /// instance of this type. This allows the main handle to still be treated as
 /// single threaded with a single multi-threadable break_loop call.
 #[cfg(feature="breakable")]
-struct HandleLifetime(*mut ffi::pcap);
+struct HandleLifetime {
+    handle: *mut ffi::pcap,
+    /// whether `pcap_loop` or `pcap_dispatch` is running, for
+    /// `LoopBreaker::is_active`
+    looping: AtomicBool,
+}
+
+/// The settings a live capture was opened with, as far as this crate has
+/// seen them. Used to reopen a device after it has gone away.
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct CaptureConfig {
+    pub device: String,
+    /// 0 means the libpcap default
+    pub snaplen: i32,
+    pub promisc: bool,
+    pub read_timeout_ms: i32,
+    /// deliver packets as they arrive instead of when the buffer fills or
+    /// the timeout expires, libpcap 1.5+
+    pub immediate_mode: bool,
+    /// fail in `open` if the device already has a handle in this process,
+    /// instead of only logging a warning; see `device_in_use`
+    pub exclusive: bool,
+}
+
+impl CaptureConfig {
+    /// libpcap's defaults for a handle made with `pcap_create`
+    pub fn new(device: &str) -> CaptureConfig {
+        CaptureConfig {
+            device: device.to_owned(),
+            snaplen: 0,
+            promisc: false,
+            read_timeout_ms: 0,
+            immediate_mode: false,
+            exclusive: false,
+        }
+    }
+
+    /// Open and activate a new handle with these settings, through
+    /// `pcap_create` and `pcap_activate`
+    pub fn open(&self) -> Result<Handle, Error> {
+        if self.exclusive && device_in_use(&self.device) {
+            return Err(Error::with_message(
+                ffi::PCAP_ERROR,
+                &format!("{} is already being captured on in this process", self.device),
+            ));
+        }
+        let mut handle = create(&self.device)?;
+        if self.snaplen > 0 {
+            handle.set_snaplen(self.snaplen as u32)?;
+        }
+        handle.set_promisc(self.promisc)?;
+        handle.set_timeout(Duration::from_millis(self.read_timeout_ms.max(0) as u64))?;
+        if self.immediate_mode {
+            handle.set_immediate_mode(true)?;
+        }
+        handle.activate()?;
+        Ok(handle)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl CaptureConfig {
+    /// `open` with promiscuous mode off, whatever `promisc` says, failing
+    /// if the interface ends up promiscuous anyway, e.g. because the driver
+    /// forces it or another capture turned it on. See
+    /// `Handle::promisc_active`.
+    pub fn strict_non_promisc(&self) -> Result<Handle, Error> {
+        let handle = CaptureConfig { promisc: false, ..self.clone() }.open()?;
+        if handle.promisc_active()? {
+            return Err(Error::with_message(
+                ffi::PCAP_ERROR,
+                &format!("{} is in promiscuous mode", self.device),
+            ));
+        }
+        Ok(handle)
+    }
+}
+
+/// Open `config.device`, run `f` with the handle, and close it again
+/// whether `f` returns or panics.
+///
+/// With the `breakable` feature the handle stays open until any
+/// `LoopBreaker` taken from it inside `f` is dropped as well.
+pub fn with_capture<T, F: FnOnce(&mut Handle) -> T>(config: &CaptureConfig, f: F) -> Result<T, Error> {
+    let mut handle = config.open()?;
+    Ok(f(&mut handle))
+}
+
+/// Devices with live handles in this process, once per handle
+static OPEN_DEVICES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// A handle's entry in `OPEN_DEVICES`, removed when the handle is dropped
+struct DeviceRegistration(String);
+
+impl DeviceRegistration {
+    fn new(device: &str) -> DeviceRegistration {
+        let mut open = OPEN_DEVICES.lock().unwrap_or_else(PoisonError::into_inner);
+        if open.iter().any(|open| open == device) {
+            warn!("{} is already being captured on in this process; packets will be seen twice", device);
+        }
+        open.push(device.to_owned());
+        DeviceRegistration(device.to_owned())
+    }
+}
+
+impl Drop for DeviceRegistration {
+    fn drop(&mut self) {
+        let mut open = OPEN_DEVICES.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(index) = open.iter().position(|open| *open == self.0) {
+            open.swap_remove(index);
+        }
+    }
+}
+
+/// Whether a handle opened by this crate in this process is capturing on
+/// `device`. Opening a device twice is allowed, but each handle gets its
+/// own copy of every packet, which is rarely intended. Handles opened by
+/// other processes, or wrapped with `Handle::from_raw`, aren't seen.
+pub fn device_in_use(device: &str) -> bool {
+    OPEN_DEVICES.lock().unwrap_or_else(PoisonError::into_inner).iter().any(|open| open == device)
+}
+
+/// The Linux pseudo-device that captures on every interface at once
+pub const ANY_DEVICE: &str = "any";
 
 pub struct Handle {
     handle: *mut ffi::pcap,
+    config: Option<CaptureConfig>,
+    timeout: Option<Duration>,
+    truncation_warnings: bool,
+    activated: bool,
+    packets_seen: Cell<u64>,
+    /// a copy of the last filter applied, for `reconfigure`
+    filter: RefCell<Option<BpfProgram>>,
+    _registration: Option<DeviceRegistration>,
     #[cfg(feature="breakable")]
     handle_lifetime: Arc<HandleLifetime>,
 }
@@ -315,17 +760,31 @@ pub struct LoopBreaker {
     _handle_lifetime: Arc<HandleLifetime>,
 }
 
-#[derive(Clone,Debug,Eq,PartialEq)]
-pub struct TimeStamp {
-    pub sec: i64,
-    pub usec: i64,
+impl TstampPrecision {
+    fn from_raw(raw: i32) -> TstampPrecision {
+        if raw == ffi::PCAP_TSTAMP_PRECISION_NANO as i32 {
+            TstampPrecision::Nano
+        } else {
+            TstampPrecision::Micro
+        }
+    }
+
+    fn as_raw(self) -> i32 {
+        match self {
+            TstampPrecision::Micro => ffi::PCAP_TSTAMP_PRECISION_MICRO as i32,
+            TstampPrecision::Nano => ffi::PCAP_TSTAMP_PRECISION_NANO as i32,
+        }
+    }
 }
 
-#[derive(Clone,Debug)]
-pub struct PacketHeader {
-    pub ts: TimeStamp,
-    pub caplen: u32,
-    pub len: u32,
+impl From<ffi::pcap_stat> for Stats {
+    fn from(stat: ffi::pcap_stat) -> Self {
+        Stats {
+            received: stat.ps_recv,
+            dropped: stat.ps_drop,
+            if_dropped: stat.ps_ifdrop,
+        }
+    }
 }
 
 unsafe impl Send for Handle{}
@@ -333,186 +792,1111 @@ unsafe impl Send for Handle{}
 #[cfg(feature="breakable")]
 unsafe impl Send for LoopBreaker{}
 
-/// Given a Rust function of type `Fn(PacketHeader, Vec<u8>)`:
+/// How the kernel spreads packets across the sockets of a `PACKET_FANOUT`
+/// group
+#[cfg(target_os = "linux")]
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum FanoutMode {
+    /// by flow hash, so each flow stays on one socket
+    Hash,
+    /// round-robin
+    LoadBalance,
+    /// by the CPU the packet arrived on
+    Cpu,
+    /// fill one socket before moving on to the next
+    Rollover,
+    Random,
+    /// by the NIC's receive queue
+    QueueMapping,
+}
+
+#[cfg(target_os = "linux")]
+impl FanoutMode {
+    fn as_raw(self) -> u32 {
+        match self {
+            FanoutMode::Hash => 0,
+            FanoutMode::LoadBalance => 1,
+            FanoutMode::Cpu => 2,
+            FanoutMode::Rollover => 3,
+            FanoutMode::Random => 4,
+            FanoutMode::QueueMapping => 5,
+        }
+    }
+}
+
+/// What `Handle::next_packet` got
+#[derive(Debug)]
+pub enum NextResult<'a> {
+    /// valid until the next call on the handle
+    Packet(PacketHeader, &'a [u8]),
+    /// the read timeout expired, or nothing was waiting in non-blocking mode
+    Timeout,
+    /// `break_loop` was called or, for a savefile, there are no more packets
+    Broken,
+}
+
+/// Which packets a capture sees, relative to the capturing host
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum Direction {
+    InOut,
+    In,
+    Out,
+}
+
+impl Direction {
+    fn as_raw(self) -> ffi::pcap_direction_t {
+        match self {
+            Direction::InOut => ffi::pcap_direction_t_PCAP_D_INOUT,
+            Direction::In => ffi::pcap_direction_t_PCAP_D_IN,
+            Direction::Out => ffi::pcap_direction_t_PCAP_D_OUT,
+        }
+    }
+}
+
+/// How `Handle::set_nonblock_or_fallback` keeps reads from waiting
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum NonblockStrategy {
+    /// `pcap_setnonblock` worked
+    NonBlocking,
+    /// reads still block, but for no longer than this read timeout
+    ShortTimeout(Duration),
+}
+
+/// Copy the header libpcap hands to a `pcap_handler` and borrow the packet data
 ///
-/// - Create a C function (of type `pcap_handler`) which allows passing arbitrary data in a *mut uchar ptr
-/// - Return tuple containing
-///     - pcap_handler C function to be passed as a callback to pcap_loop
-///     - *mut uchar ptr to the Rust function to be passed as extra data to pcap_loop
+/// Packets cut short by `snaplen` are only logged at debug level, since a
+/// small snaplen is a deliberate choice; any other truncation is a warning.
+/// Neither is logged if `warn_truncated` is false.
+///
+/// A crafted savefile can claim a `caplen` larger than the snaplen or the
+/// packet's `len`; libpcap may not have filled a buffer that large, so
+/// `caplen` is clamped to both before the data is sliced, and the returned
+/// header carries the clamped value.
+///
+/// An empty packet may come with a null data pointer, which must not reach
+/// `slice::from_raw_parts` even for length 0; a null pointer is taken to
+/// mean no data was captured at all.
+unsafe fn convert_packet<'a>(
+    header: *const ffi::pcap_pkthdr,
+    packet: *const libc::c_uchar,
+    snaplen: Option<u32>,
+    warn_truncated: bool,
+) -> (PacketHeader, &'a [u8]) {
+    let len = (*header).len;
+    let mut caplen = if packet.is_null() { 0 } else { (*header).caplen };
+    if let Some(snaplen) = snaplen.filter(|&snaplen| snaplen > 0 && caplen > snaplen) {
+        warn!("caplen {} is larger than the snaplen {}, clamping", caplen, snaplen);
+        caplen = snaplen;
+    }
+    if caplen > len {
+        warn!("caplen {} is larger than the packet length {}, clamping", caplen, len);
+        caplen = len;
+    }
+    if caplen >= len || !warn_truncated {
+        // nothing to report
+    } else if matches!(snaplen, Some(snaplen) if caplen >= snaplen) {
+        debug!("packet truncated to snaplen: len={}, caplen={}", len, caplen);
+    } else {
+        warn!(
+            "WARNING: Didn't capture entire packet: len={}, caplen={}",
+            len, caplen
+        );
+    }
+
+    let packet = if caplen == 0 { &[] } else { slice::from_raw_parts(packet, caplen as _) };
+    let header = PacketHeader {
+        ts: TimeStamp {
+            sec: (*header).ts.tv_sec as i64,
+            usec: (*header).ts.tv_usec as i64,
+        },
+        caplen,
+        len,
+    };
+    (header, packet)
+}
+
+/// A Rust closure packaged as a `pcap_handler` and its user data.
 ///
-/// Inside the C callback, cast the extra data back to the Rust function and call it
+/// A panic must not unwind through `pcap_loop`/`pcap_dispatch`, which are C
+/// frames. The generated handler catches it, stores it, and asks libpcap to
+/// stop; `finish` re-raises it once the C function has returned. Packets
+/// still delivered before libpcap notices the break are skipped.
 ///
 /// C callback definition:
 /// void got_packet(uchar *args, const struct pcap_pkthdr *header, const u_char *packet);
-fn convert_got_packet_cb<F: FnMut(*const ffi::pcap_pkthdr, *const libc::c_uchar)>(
-    got_packet_rs: &mut F,
-) -> (ffi::pcap_handler, *mut libc::c_uchar) {
-    unsafe extern "C" fn got_packet<F: FnMut(*const ffi::pcap_pkthdr, *const libc::c_uchar)>(
-        user_data: *mut libc::c_uchar,
-        header: *const ffi::pcap_pkthdr,
-        packet: *const libc::c_uchar,
-    ) {
-        let got_packet_rs = user_data as *mut F;
-
-        (*got_packet_rs)(header, packet);
-    }
-
-    (
-        Some(got_packet::<F>),
-        got_packet_rs as *mut F as *mut libc::c_uchar,
-    )
-}
-
-impl Into<SystemTime> for TimeStamp {
-    fn into(self) -> std::time::SystemTime {
-        SystemTime::UNIX_EPOCH + Duration::new(
-            self.sec as u64,
-            (self.usec * 1000) as u32
+struct PacketCallback<F> {
+    f: F,
+    handle: *mut ffi::pcap,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl<F: FnMut(*const ffi::pcap_pkthdr, *const libc::c_uchar)> PacketCallback<F> {
+    fn new(handle: *mut ffi::pcap, f: F) -> PacketCallback<F> {
+        PacketCallback { f, handle, panic: None }
+    }
+
+    /// The handler and user data to pass to libpcap. `self` must stay where
+    /// it is until libpcap returns.
+    fn as_raw(&mut self) -> (ffi::pcap_handler, *mut libc::c_uchar) {
+        unsafe extern "C" fn got_packet<F: FnMut(*const ffi::pcap_pkthdr, *const libc::c_uchar)>(
+            user_data: *mut libc::c_uchar,
+            header: *const ffi::pcap_pkthdr,
+            packet: *const libc::c_uchar,
+        ) {
+            let callback = &mut *(user_data as *mut PacketCallback<F>);
+            if callback.panic.is_some() {
+                return;
+            }
+            let f = &mut callback.f;
+            if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| f(header, packet))) {
+                callback.panic = Some(panic);
+                ffi::pcap_breakloop(callback.handle);
+            }
+        }
+
+        (
+            Some(got_packet::<F>),
+            self as *mut PacketCallback<F> as *mut libc::c_uchar,
         )
     }
+
+    /// Resume a panic caught in the closure
+    fn finish(self) {
+        if let Some(panic) = self.panic {
+            panic::resume_unwind(panic)
+        }
+    }
 }
 
 impl Handle {
     fn new(handle: *mut ffi::pcap) -> Handle {
         Handle {
             handle,
+            config: None,
+            timeout: None,
+            truncation_warnings: true,
+            activated: true,
+            packets_seen: Cell::new(0),
+            filter: RefCell::new(None),
+            _registration: None,
             #[cfg(feature="breakable")]
-            handle_lifetime: Arc::new(HandleLifetime(handle))
+            handle_lifetime: Arc::new(HandleLifetime {
+                handle,
+                looping: AtomicBool::new(false),
+            })
         }
     }
 
+    /// Wrap a `pcap_t` opened by other code, e.g. a vendor extension.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be a valid, non-null `pcap_t` that nothing else will
+    /// close. The `Handle` takes ownership and calls `pcap_close` when it is
+    /// dropped (with `breakable`, when its last `LoopBreaker` is dropped), so
+    /// the caller must not close it and must not use the pointer after that.
+    pub unsafe fn from_raw(handle: *mut ffi::pcap) -> Handle {
+        Handle::new(handle)
+    }
+
+    /// The underlying `pcap_t`, for calling libpcap functions this crate
+    /// doesn't wrap. It stays owned by the `Handle`: don't close it, and
+    /// don't keep it past the `Handle`'s lifetime.
+    pub fn as_raw(&self) -> *mut ffi::pcap {
+        self.handle
+    }
+
+    fn with_config(handle: *mut ffi::pcap, config: CaptureConfig) -> Handle {
+        let mut handle = Handle::new(handle);
+        handle._registration = Some(DeviceRegistration::new(&config.device));
+        handle.config = Some(config);
+        handle
+    }
+
     fn chkerr(&self, code: i32) -> Result<(),Error> {
         Error::check(self.handle, code)
     }
 
+    /// How this handle was opened, if it refers to a live device
+    pub fn config(&self) -> Option<&CaptureConfig> {
+        self.config.as_ref()
+    }
+
     pub fn datalink(&self) -> i32 {
         unsafe { ffi::pcap_datalink(self.handle) }
     }
 
-    pub fn break_loop(&self) {
-        unsafe { ffi::pcap_breakloop(self.handle) }
+    /// int pcap_datalink_ext(pcap_t *p)
+    ///
+    /// Bytes of frame check sequence at the end of each packet, if known.
+    /// Only savefiles can record it; see `link::fcs_len`.
+    pub fn fcs_len(&self) -> Option<usize> {
+        link::fcs_len(unsafe { ffi::pcap_datalink_ext(self.handle) })
     }
 
-    #[cfg(feature="breakable")]
-    pub fn loop_breaker(&self) -> LoopBreaker {
-        LoopBreaker{
-            handle: self.handle,
-            _handle_lifetime: self.handle_lifetime.clone()
-        }
+    /// Whether packets end in a frame check sequence, if known; strip it
+    /// with `link::strip_fcs`
+    pub fn has_fcs(&self) -> Option<bool> {
+        self.fcs_len().map(|len| len > 0)
     }
 
-    pub fn loop_<F: FnMut(PacketHeader, &[u8])>(&self, count: i32, mut f: F) {
-        self._loop(count, move |header, packet| {
-            let len = unsafe { (*header).len };
-            let caplen = unsafe { (*header).caplen };
-            if caplen < len {
-                log::warn!(
-                    "WARNING: Didn't capture entire packet: len={}, caplen={}",
-                    len, caplen
-                );
-            }
-
-            let packet = unsafe { slice::from_raw_parts(packet, caplen as _) };
-            let header = unsafe {
-                PacketHeader {
-                    ts: TimeStamp {
-                        sec: (*header).ts.tv_sec as i64,
-                        usec: (*header).ts.tv_usec as i64,
-                    },
-                    caplen: (*header).caplen,
-                    len: (*header).len,
-                }
-            };
-
-            f(header, packet);
-        });
+    /// int pcap_snapshot(pcap_t *p)
+    ///
+    /// The snaplen the handle actually uses, which is what decides whether
+    /// a packet with `caplen < len` was cut short deliberately. It can
+    /// differ from the one requested: libpcap replaces 0 and out-of-range
+    /// values with its maximum, and some link types have a smaller one.
+    /// Fails with `ErrorKind::NotActivated` before activation, when there
+    /// is nothing authoritative to report yet.
+    pub fn snapshot(&self) -> Result<i32,Error> {
+        match unsafe { ffi::pcap_snapshot(self.handle) } {
+            rc if rc < 0 => Err(Error::from_last(self.handle, rc)),
+            snaplen => Ok(snaplen),
+        }
     }
 
-    fn _loop<F: FnMut(*const ffi::pcap_pkthdr, *const libc::c_uchar)>(
-        &self,
-        count: i32,
-        mut got_packet_rs: F,
-    ) {
-        let (got_packet, user_data) = convert_got_packet_cb(&mut got_packet_rs);
+    /// int pcap_is_swapped(pcap_t *p)
+    ///
+    /// For savefiles, whether the file is in the opposite byte order to this host.
+    pub fn is_swapped(&self) -> Result<bool,Error> {
+        match unsafe { ffi::pcap_is_swapped(self.handle) } {
+            rc if rc < 0 => Err(Error::from_last(self.handle, rc)),
+            swapped => Ok(swapped != 0),
+        }
+    }
 
+    /// The savefile format version, `(pcap_major_version, pcap_minor_version)`.
+    ///
+    /// libpcap doesn't expose the `thiszone` and `sigfigs` header fields; use
+    /// `savefile::PcapReader` if you need those.
+    pub fn version(&self) -> (i32, i32) {
         unsafe {
-            ffi::pcap_loop(self.handle, count, got_packet, user_data);
+            (ffi::pcap_major_version(self.handle), ffi::pcap_minor_version(self.handle))
         }
     }
 
-    /// int pcap_compile(pcap_t *p, struct bpf_program *fp, char *str, int optimize, bpf_u_int32 netmask)
-    pub fn compile(&self, filter: &str, optimize: bool, netmask: u32) -> Result<ffi::bpf_program,Error> {
-        let mut bpf_program = MaybeUninit::<ffi::bpf_program>::uninit();
-        let filter = CString::new(filter).unwrap();
-        let res = unsafe {
-            ffi::pcap_compile(
-                self.handle,
-                bpf_program.as_mut_ptr(),
-                filter.as_ptr(),
-                optimize as i32,
-                netmask,
-            )
-        };
-        self.chkerr(res).map(|_|unsafe { bpf_program.assume_init() })
+    pub fn datalink_type(&self) -> DataLinkType {
+        DataLinkType::from_raw(self.datalink())
     }
 
-    pub fn set_filter(&self, filter: &mut ffi::bpf_program) -> Result<(),Error> {
-        self.chkerr(unsafe { ffi::pcap_setfilter(self.handle, filter) })
+    /// See `DataLinkType::link_header_kind`
+    pub fn link_header_kind(&self) -> LinkHeaderKind {
+        self.datalink_type().link_header_kind()
     }
 
-    pub fn set_nonblock(&mut self, non_blocking: bool) -> Result<(),Error> {
-        let mut err_buf = ErrBuf::new();
-        let res = unsafe {
-            ffi::pcap_setnonblock(
-                self.handle,
-                if non_blocking { 1 } else { 0  },
-                err_buf.as_raw_ptr()
-            )
-        };
-        if res != 0 {
-            Err(Error::new(err_buf, 1))
-        } else {
-            Ok(())
+    /// See `DataLinkType::link_header_len`
+    pub fn link_header_len(&self) -> Option<usize> {
+        self.datalink_type().link_header_len()
+    }
+
+    /// int pcap_list_datalinks(pcap_t *p, int **dlt_buf)
+    pub fn list_datalinks(&self) -> Result<Vec<i32>,Error> {
+        let mut dlts: *mut libc::c_int = std::ptr::null_mut();
+        let count = unsafe { ffi::pcap_list_datalinks(self.handle, &mut dlts) };
+        if count < 0 {
+            return Err(Error::from_last(self.handle, count));
         }
+        if dlts.is_null() {
+            return Ok(vec![]);
+        }
+        let list = unsafe { slice::from_raw_parts(dlts, count as usize).to_vec() };
+        unsafe { ffi::pcap_free_datalinks(dlts) };
+        Ok(list)
     }
 
-    pub fn set_snaplen(&mut self, snaplen: u32) -> Result<(),Error> {
-        self.chkerr(unsafe {
-            ffi::pcap_set_snaplen(
-                self.handle,
-                snaplen as i32
-            )
-        })
+    /// Whether this handle was opened on the `any` pseudo-device, whose
+    /// packets start with a Linux cooked-capture header, not Ethernet
+    pub fn is_any_device(&self) -> bool {
+        matches!(&self.config, Some(config) if config.device == ANY_DEVICE)
     }
 
-    pub fn set_promisc(&mut self, promisc: bool) -> Result<(),Error> {
-        self.chkerr(unsafe {
-            ffi::pcap_set_promisc(
-                self.handle,
-                if promisc { 1 } else { 0 }
-            )
-        })
+    /// The link types `set_datalink` accepts for this device
+    pub fn supported_link_types(&self) -> Result<Vec<DataLinkType>,Error> {
+        Ok(self.list_datalinks()?.into_iter().map(DataLinkType::from_raw).collect())
     }
 
-    pub fn activate(&mut self) -> Result<(),Error> {
-        self.chkerr(unsafe {
-            ffi::pcap_activate(self.handle)
-        })
+    /// int pcap_set_datalink(pcap_t *p, int dlt)
+    pub fn set_datalink<D: Into<DataLinkType>>(&mut self, dlt: D) -> Result<&mut Self,Error> {
+        self.chkerr(unsafe { ffi::pcap_set_datalink(self.handle, dlt.into().as_raw()) })?;
+        Ok(self)
     }
-}
 
-#[cfg(feature="breakable")]
-impl LoopBreaker {
+    /// `set_datalink` with the link type looked up by `datalink_name_to_val`,
+    /// for names taken from configuration
+    pub fn set_datalink_by_name(&mut self, name: &str) -> Result<&mut Self,Error> {
+        let dlt = datalink_name_to_val(name)?;
+        self.set_datalink(dlt)
+    }
+
+    /// void pcap_breakloop(pcap_t *p)
+    ///
+    /// Makes the running `loop_` or `dispatch` return early. libpcap only
+    /// clears the request when a loop returns because of it: a `loop_` that
+    /// was broken can be started again straight away, but a break requested
+    /// while no loop is running, or from the callback of the last packet a
+    /// `dispatch` or bounded `loop_` was going to process anyway, stays
+    /// pending and ends the next `loop_` or `dispatch` before it delivers
+    /// any packets. There is no way to withdraw a request; call this only
+    /// while a loop is running, or be prepared for one empty run.
     pub fn break_loop(&self) {
         unsafe { ffi::pcap_breakloop(self.handle) }
     }
-}
 
-#[cfg(feature="breakable")]
-impl Drop for HandleLifetime {
-    fn drop(&mut self) {
-        unsafe { ffi::pcap_close(self.0) }
-    }
+    /// int pcap_sendpacket(pcap_t *p, const u_char *buf, int size)
+    pub fn send_packet(&mut self, data: &[u8]) -> Result<(),Error> {
+        self.chkerr(unsafe {
+            ffi::pcap_sendpacket(self.handle, data.as_ptr(), data.len() as i32)
+        })
+    }
+
+    /// int pcap_inject(pcap_t *p, const void *buf, size_t size)
+    ///
+    /// Returns the number of bytes written.
+    pub fn inject(&mut self, data: &[u8]) -> Result<usize,Error> {
+        match unsafe { ffi::pcap_inject(self.handle, data.as_ptr() as *const libc::c_void, data.len()) } {
+            rc if rc < 0 => Err(Error::from_last(self.handle, rc)),
+            written => Ok(written as usize),
+        }
+    }
+
+    /// Transmit several frames, returning how many were accepted.
+    ///
+    /// On Linux the frames are handed to the capture socket with `sendmmsg`,
+    /// so a batch costs one system call per 1024 frames instead of one per
+    /// frame. Elsewhere this is a loop over `send_packet`. An error is only
+    /// returned if not even the first frame could be sent; a short count
+    /// means the frame after the last accepted one failed.
+    pub fn send_batch(&mut self, frames: &[&[u8]]) -> Result<usize,Error> {
+        #[cfg(target_os = "linux")]
+        {
+            let fd = unsafe { ffi::pcap_get_selectable_fd(self.handle) };
+            if fd >= 0 {
+                return sendmmsg_all(fd, frames);
+            }
+        }
+
+        let mut sent = 0;
+        for frame in frames {
+            match self.send_packet(frame) {
+                Ok(()) => sent += 1,
+                Err(err) if sent == 0 => return Err(err),
+                Err(_) => break,
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Join the capture socket to `PACKET_FANOUT` group `group_id`, so the
+    /// kernel shares the interface's packets out among every socket in the
+    /// group instead of copying them to each. The handle must be activated.
+    ///
+    /// With `defrag` set, IP fragments are reassembled before the fanout
+    /// decision so that all fragments of a datagram go to the same socket.
+    #[cfg(target_os = "linux")]
+    pub fn set_fanout(&mut self, group_id: u16, mode: FanoutMode, defrag: bool) -> Result<&mut Self,Error> {
+        const SOL_PACKET: libc::c_int = 263;
+        const PACKET_FANOUT: libc::c_int = 18;
+        const PACKET_FANOUT_FLAG_DEFRAG: u32 = 0x8000;
+
+        let fd = unsafe { ffi::pcap_get_selectable_fd(self.handle) };
+        if fd < 0 {
+            return Err(Error::with_message(ffi::PCAP_ERROR, "handle has no capture socket"));
+        }
+        let flags = if defrag { PACKET_FANOUT_FLAG_DEFRAG } else { 0 };
+        let arg: u32 = u32::from(group_id) | (mode.as_raw() | flags) << 16;
+        let rc = unsafe {
+            libc::setsockopt(
+                fd,
+                SOL_PACKET,
+                PACKET_FANOUT,
+                &arg as *const u32 as *const libc::c_void,
+                std::mem::size_of::<u32>() as libc::socklen_t,
+            )
+        };
+        if rc != 0 {
+            let err = std::io::Error::last_os_error();
+            return Err(Error::with_message(ffi::PCAP_ERROR, &format!("PACKET_FANOUT: {}", err)));
+        }
+        Ok(self)
+    }
+
+    /// int pcap_get_tstamp_precision(pcap_t *p)
+    pub fn tstamp_precision(&self) -> TstampPrecision {
+        TstampPrecision::from_raw(unsafe { ffi::pcap_get_tstamp_precision(self.handle) })
+    }
+
+    /// pcap_dumper_t *pcap_dump_open(pcap_t *p, const char *fname)
+    ///
+    /// The savefile header takes its link type, snaplen and timestamp
+    /// precision from this handle.
+    pub fn dump_open<P: AsRef<Path>>(&self, path: P) -> Result<Dumper,Error> {
+        let path = path_to_cstring(path.as_ref())?;
+        let dumper = unsafe { ffi::pcap_dump_open(self.handle, path.as_ptr()) };
+        if dumper.is_null() {
+            Err(Error::from_last(self.handle, ffi::PCAP_ERROR))
+        } else {
+            Ok(Dumper {
+                dumper,
+                precision: self.tstamp_precision(),
+            })
+        }
+    }
+
+    /// pcap_dumper_t *pcap_dump_fopen(pcap_t *p, FILE *fp)
+    ///
+    /// `dump_open` on a file that is already open, e.g. an anonymous
+    /// temporary file or an inherited descriptor. The dumper takes ownership
+    /// of `fd` and closes it when dropped, also if this fails.
+    #[cfg(unix)]
+    pub fn dump_open_fd<F: IntoRawFd>(&self, fd: F) -> Result<Dumper,Error> {
+        let fd = fd.into_raw_fd();
+        let file = unsafe { libc::fdopen(fd, b"w\0".as_ptr() as *const libc::c_char) };
+        if file.is_null() {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err.into());
+        }
+        let dumper = unsafe { ffi::pcap_dump_fopen(self.handle, file) };
+        if dumper.is_null() {
+            // libpcap leaves the file open when it fails
+            let err = Error::from_last(self.handle, ffi::PCAP_ERROR);
+            unsafe { libc::fclose(file) };
+            Err(err)
+        } else {
+            Ok(Dumper {
+                dumper,
+                precision: self.tstamp_precision(),
+            })
+        }
+    }
+
+    /// int pcap_can_set_rfmon(pcap_t *p)
+    pub fn can_set_rfmon(&self) -> Result<bool,Error> {
+        match unsafe { ffi::pcap_can_set_rfmon(self.handle) } {
+            0 => Ok(false),
+            1 => Ok(true),
+            rc => Err(Error::from_last(self.handle, rc)),
+        }
+    }
+
+    /// int pcap_stats(pcap_t *p, struct pcap_stat *ps)
+    pub fn stats(&self) -> Result<Stats,Error> {
+        let mut stat = MaybeUninit::<ffi::pcap_stat>::uninit();
+        let res = unsafe { ffi::pcap_stats(self.handle, stat.as_mut_ptr()) };
+        self.chkerr(res).map(|_| Stats::from(unsafe { stat.assume_init() }))
+    }
+
+    #[cfg(feature="breakable")]
+    pub fn loop_breaker(&self) -> LoopBreaker {
+        LoopBreaker{
+            handle: self.handle,
+            _handle_lifetime: self.handle_lifetime.clone()
+        }
+    }
+
+    /// Packets delivered by `loop_`, `dispatch` and `next_packet` so far.
+    ///
+    /// Counted by this crate rather than libpcap, so unlike `stats` it works
+    /// for savefiles too, e.g. for progress reporting. Packets rejected by
+    /// the filter are never delivered and so aren't counted.
+    pub fn packets_seen(&self) -> u64 {
+        self.packets_seen.get()
+    }
+
+    /// Process `count` packets (forever if `count` is 0 or negative).
+    ///
+    /// Returns `Ok` once `count` packets have been processed, the end of a
+    /// savefile is reached or `break_loop` is called.
+    pub fn loop_<F: FnMut(PacketHeader, &[u8])>(&self, count: i32, mut f: F) -> Result<(),Error> {
+        let snaplen = self.snapshot().ok().map(|snaplen| snaplen as u32);
+        let warn_truncated = self.truncation_warnings;
+        let seen = &self.packets_seen;
+        self._loop(count, move |header, packet| {
+            let (header, packet) = unsafe { convert_packet(header, packet, snaplen, warn_truncated) };
+            seen.set(seen.get() + 1);
+            f(header, packet);
+        })
+    }
+
+    /// int pcap_next_ex(pcap_t *p, struct pcap_pkthdr **pkt_header, const u_char **pkt_data)
+    ///
+    /// Read one packet. A deliberate stop is `Ok(NextResult::Broken)`, not an
+    /// error, so a polling loop can tell it from a failure; libpcap reports
+    /// the end of a savefile the same way.
+    ///
+    /// libpcap reuses the packet buffer on the next read, so the packet
+    /// borrows the handle mutably and must be gone before the next call:
+    ///
+    /// ```compile_fail,E0499
+    /// # use rustcap::core::open_offline;
+    /// let mut handle = open_offline("capture.pcap").unwrap();
+    /// let first = handle.next_packet().unwrap();
+    /// let second = handle.next_packet().unwrap();
+    /// println!("{:?} {:?}", first, second);
+    /// ```
+    pub fn next_packet(&mut self) -> Result<NextResult<'_>,Error> {
+        let mut header: *mut ffi::pcap_pkthdr = ptr::null_mut();
+        let mut packet: *const libc::c_uchar = ptr::null();
+        match unsafe { ffi::pcap_next_ex(self.handle, &mut header, &mut packet) } {
+            1 => {
+                let snaplen = self.snapshot().ok().map(|snaplen| snaplen as u32);
+                let (header, packet) = unsafe {
+                    convert_packet(header, packet, snaplen, self.truncation_warnings)
+                };
+                self.packets_seen.set(self.packets_seen.get() + 1);
+                Ok(NextResult::Packet(header, packet))
+            }
+            0 => Ok(NextResult::Timeout),
+            ffi::PCAP_ERROR_BREAK => Ok(NextResult::Broken),
+            rc => Err(Error::from_last(self.handle, rc)),
+        }
+    }
+
+    /// Process packets until at least `max_bytes` of captured data (the sum
+    /// of their `caplen`s) has been delivered, returning the total.
+    ///
+    /// The packet that reaches the limit is still passed to `f`, so the total
+    /// can exceed `max_bytes` by up to one snaplen. Also returns early at the
+    /// end of a savefile or on `break_loop`.
+    pub fn loop_bytes<F: FnMut(PacketHeader, &[u8])>(&self, max_bytes: u64, mut f: F) -> Result<u64,Error> {
+        let mut total = 0;
+        if max_bytes == 0 {
+            return Ok(total);
+        }
+        self.loop_(0, |header, packet| {
+            total += u64::from(header.caplen);
+            f(header, packet);
+            if total >= max_bytes {
+                self.break_loop();
+            }
+        })?;
+        Ok(total)
+    }
+
+    /// `loop_`, handing `f` the first `N` bytes of each packet in a buffer
+    /// that is reused for every packet, so nothing is allocated, along with
+    /// how many of them were captured. Bytes past that count are zero.
+    ///
+    /// Set the snaplen to `N` as well if the rest of the packet is never
+    /// needed, so it isn't copied out of the kernel either.
+    pub fn loop_header<const N: usize, F: FnMut(PacketHeader, &[u8; N], usize)>(
+        &self,
+        count: i32,
+        mut f: F,
+    ) -> Result<(),Error> {
+        let mut buf = [0u8; N];
+        self.loop_(count, |header, packet| {
+            let len = packet.len().min(N);
+            buf[..len].copy_from_slice(&packet[..len]);
+            buf[len..].fill(0);
+            f(header, &buf, len);
+        })
+    }
+
+    /// Process at most one buffer's worth of packets, up to `count` (all of
+    /// them if `count` is 0 or negative), returning how many were processed.
+    ///
+    /// In non-blocking mode this returns `Ok(0)` when nothing is waiting.
+    pub fn dispatch<F: FnMut(PacketHeader, &[u8])>(&self, count: i32, mut f: F) -> Result<usize,Error> {
+        let snaplen = self.snapshot().ok().map(|snaplen| snaplen as u32);
+        let warn_truncated = self.truncation_warnings;
+        let seen = &self.packets_seen;
+        let mut callback = PacketCallback::new(self.handle, move |header, packet| {
+            let (header, packet) = unsafe { convert_packet(header, packet, snaplen, warn_truncated) };
+            seen.set(seen.get() + 1);
+            f(header, packet);
+        });
+        let (got_packet, user_data) = callback.as_raw();
+        self.set_looping(true);
+        let rc = unsafe { ffi::pcap_dispatch(self.handle, count, got_packet, user_data) };
+        self.set_looping(false);
+        callback.finish();
+
+        match rc {
+            rc if rc >= 0 => Ok(rc as usize),
+            ffi::PCAP_ERROR_BREAK => Ok(0),
+            rc => Err(Error::from_last(self.handle, rc)),
+        }
+    }
+
+    #[cfg(feature="breakable")]
+    fn set_looping(&self, looping: bool) {
+        self.handle_lifetime.looping.store(looping, Ordering::Release)
+    }
+
+    #[cfg(not(feature="breakable"))]
+    fn set_looping(&self, _looping: bool) {}
+
+    fn _loop<F: FnMut(*const ffi::pcap_pkthdr, *const libc::c_uchar)>(
+        &self,
+        count: i32,
+        got_packet_rs: F,
+    ) -> Result<(),Error> {
+        let mut callback = PacketCallback::new(self.handle, got_packet_rs);
+        let (got_packet, user_data) = callback.as_raw();
+        self.set_looping(true);
+        let rc = unsafe { ffi::pcap_loop(self.handle, count, got_packet, user_data) };
+        self.set_looping(false);
+        callback.finish();
+
+        match rc {
+            rc if rc >= 0 || rc == ffi::PCAP_ERROR_BREAK => Ok(()),
+            rc => Err(Error::from_last(self.handle, rc)),
+        }
+    }
+
+    /// int pcap_compile(pcap_t *p, struct bpf_program *fp, char *str, int optimize, bpf_u_int32 netmask)
+    pub fn compile(&self, filter: &str, optimize: bool, netmask: u32) -> Result<BpfProgram,Error> {
+        let mut bpf_program = MaybeUninit::<ffi::bpf_program>::uninit();
+        let filter = CString::new(filter).unwrap();
+        let res = unsafe {
+            ffi::pcap_compile(
+                self.handle,
+                bpf_program.as_mut_ptr(),
+                filter.as_ptr(),
+                optimize as i32,
+                netmask,
+            )
+        };
+        self.chkerr(res).map(|_| BpfProgram::compiled(unsafe { bpf_program.assume_init() }))
+    }
+
+    /// `compile` with and without the optimizer, to show side by side what
+    /// it does to a filter through `BpfProgram::instructions`
+    pub fn compile_both(&self, filter: &str, netmask: u32) -> Result<CompiledFilters,Error> {
+        Ok(CompiledFilters {
+            optimized: self.compile(filter, true, netmask)?,
+            unoptimized: self.compile(filter, false, netmask)?,
+        })
+    }
+
+    /// int pcap_setfilter(pcap_t *p, struct bpf_program *fp)
+    ///
+    /// libpcap keeps its own copy of the program, so `filter` can be dropped
+    /// or applied to other handles afterwards.
+    pub fn set_filter(&self, filter: &BpfProgram) -> Result<(),Error> {
+        // pcap_setfilter only reads through the pointer
+        let program = &filter.program as *const ffi::bpf_program as *mut ffi::bpf_program;
+        self.chkerr(unsafe { ffi::pcap_setfilter(self.handle, program) })?;
+        *self.filter.borrow_mut() = Some(BpfProgram::from_instructions(filter.instructions()));
+        Ok(())
+    }
+
+    /// The netmask to compile filters with: that of the first IPv4 address
+    /// of the device this handle captures on, in network byte order as
+    /// `pcap_lookupnet` would give it, or `PCAP_NETMASK_UNKNOWN` for
+    /// savefiles and devices without one. Filters such as `ip broadcast`
+    /// only work with the real netmask.
+    pub fn netmask_for_filter(&self) -> u32 {
+        let device = match &self.config {
+            Some(config) => &config.device,
+            None => return ffi::PCAP_NETMASK_UNKNOWN,
+        };
+        find_all_devs()
+            .ok()
+            .and_then(|mut devs| devs.find(|dev| dev.name() == device))
+            .and_then(|dev| {
+                dev.addresses().iter().find_map(|address| match (address.address, address.netmask) {
+                    (Some(SocketAddr::V4(_)), Some(SocketAddr::V4(netmask))) => {
+                        Some(u32::from_ne_bytes(netmask.ip().octets()))
+                    }
+                    _ => None,
+                })
+            })
+            .unwrap_or(ffi::PCAP_NETMASK_UNKNOWN)
+    }
+
+    /// Compile `filter` (optimized, with `netmask_for_filter`) and apply it
+    pub fn apply_filter(&self, filter: &str) -> Result<(),Error> {
+        let program = self.compile(filter, true, self.netmask_for_filter())?;
+        self.set_filter(&program)
+    }
+
+    /// int pcap_setdirection(pcap_t *p, pcap_direction_t d)
+    ///
+    /// Not supported on every platform; Windows in particular rejects it.
+    pub fn set_direction(&mut self, direction: Direction) -> Result<&mut Self,Error> {
+        self.chkerr(unsafe { ffi::pcap_setdirection(self.handle, direction.as_raw()) })?;
+        Ok(self)
+    }
+
+    /// int pcap_setnonblock(pcap_t *p, int nonblock, char *errbuf)
+    ///
+    /// In non-blocking mode `dispatch` returns `Ok(0)` instead of waiting when
+    /// no packets are buffered; `loop_` keeps polling and burns CPU. On Windows,
+    /// Npcap emulates this by switching the driver's read timeout to
+    /// "return immediately" and restores the configured timeout when turned
+    /// off, so the timeout set before activation still applies afterwards.
+    /// Because older WinPcap builds didn't always do this, on Windows the
+    /// mode is read back and an error is returned if it didn't take effect.
+    /// See `set_nonblock_or_fallback` for backends without non-blocking mode.
+    pub fn set_nonblock(&mut self, non_blocking: bool) -> Result<&mut Self,Error> {
+        let mut err_buf = ErrBuf::new();
+        let res = unsafe {
+            ffi::pcap_setnonblock(
+                self.handle,
+                if non_blocking { 1 } else { 0  },
+                err_buf.as_raw_ptr()
+            )
+        };
+        if res != 0 {
+            return Err(Error::new(err_buf, ffi::PCAP_ERROR));
+        }
+        #[cfg(windows)]
+        {
+            if self.get_nonblock()? != non_blocking {
+                return Err(Error::with_message(ffi::PCAP_ERROR, "the driver did not change the non-blocking mode"));
+            }
+        }
+        Ok(self)
+    }
+
+    /// Discard the packets already waiting in the capture buffer, e.g. the
+    /// backlog that built up between activation and the start of a
+    /// measurement, and return how many there were.
+    ///
+    /// Drains with non-blocking `dispatch` until nothing is left or a
+    /// packet captured after the call shows up, so a busy link can't keep
+    /// it going forever; the rest of that last batch is discarded too. The
+    /// blocking mode is restored afterwards, even if draining failed, and
+    /// the discarded packets don't count towards `packets_seen`. If both
+    /// fail, the draining error is returned. Only for live captures.
+    pub fn flush_buffered(&mut self) -> Result<usize,Error> {
+        if self.config.is_none() {
+            return Err(Error::with_message(ffi::PCAP_ERROR, "only live captures have a buffer to flush"));
+        }
+        let started = TimeStamp::from_system_time(SystemTime::now(), self.tstamp_precision())
+            .map_err(|err| Error::with_message(ffi::PCAP_ERROR, &err.to_string()))?;
+        let was_non_blocking = self.get_nonblock()?;
+        if !was_non_blocking {
+            self.set_nonblock(true)?;
+        }
+        let seen = self.packets_seen.get();
+        let mut flushed = 0;
+        let drained = loop {
+            let mut caught_up = false;
+            match self.dispatch(-1, |header, _| caught_up |= header.ts >= started) {
+                Ok(0) => break Ok(flushed),
+                Ok(count) => flushed += count,
+                Err(err) => break Err(err),
+            }
+            if caught_up {
+                break Ok(flushed);
+            }
+        };
+        self.packets_seen.set(seen);
+        let restored = if was_non_blocking { Ok(()) } else { self.set_nonblock(false).map(|_| ()) };
+        // a drain error is the more useful one to report
+        drained.and_then(|flushed| restored.map(|()| flushed))
+    }
+
+    /// int pcap_getnonblock(pcap_t *p, char *errbuf)
+    ///
+    /// Always `false` for savefiles.
+    pub fn get_nonblock(&self) -> Result<bool,Error> {
+        let mut err_buf = ErrBuf::new();
+        match unsafe { ffi::pcap_getnonblock(self.handle, err_buf.as_raw_ptr()) } {
+            rc if rc < 0 => Err(Error::new(err_buf, rc)),
+            rc => Ok(rc != 0),
+        }
+    }
+
+    /// int pcap_set_snaplen(pcap_t *p, int snaplen)
+    ///
+    /// A snaplen above `MAX_SNAPLEN` is rejected rather than silently
+    /// lowered by libpcap; see `link::snaplen_for_mtu` for sizing one for
+    /// jumbo frames. 0 selects the maximum.
+    pub fn set_snaplen(&mut self, snaplen: u32) -> Result<&mut Self,Error> {
+        if snaplen > MAX_SNAPLEN {
+            return Err(Error::with_message(ffi::PCAP_ERROR, &format!(
+                "snaplen {} is larger than the maximum of {}", snaplen, MAX_SNAPLEN
+            )));
+        }
+        self.chkerr(unsafe {
+            ffi::pcap_set_snaplen(
+                self.handle,
+                snaplen as i32
+            )
+        })?;
+        if let Some(config) = &mut self.config {
+            config.snaplen = snaplen as i32;
+        }
+        Ok(self)
+    }
+
+    /// Capture only the first `bytes` of each packet, e.g. enough for the
+    /// link, IP and transport headers when doing flow accounting. Must be
+    /// called before `activate`.
+    ///
+    /// Truncation caused by the snaplen isn't reported as a warning, so this
+    /// won't flood the log the way `caplen < len` otherwise would.
+    pub fn header_only(&mut self, bytes: u32) -> Result<&mut Self,Error> {
+        self.set_snaplen(bytes)
+    }
+
+    /// int pcap_set_promisc(pcap_t *p, int promisc)
+    ///
+    /// Only before activation; afterwards this fails with
+    /// `ErrorKind::AlreadyActivated` and the recorded `CaptureConfig` is left
+    /// alone. Use `promisc_active` to see what the interface is doing.
+    pub fn set_promisc(&mut self, promisc: bool) -> Result<&mut Self,Error> {
+        if self.activated {
+            return Err(Error::with_message(
+                ffi::PCAP_ERROR_ACTIVATED,
+                "promiscuous mode can only be changed before activation",
+            ));
+        }
+        self.chkerr(unsafe {
+            ffi::pcap_set_promisc(
+                self.handle,
+                if promisc { 1 } else { 0 }
+            )
+        })?;
+        if let Some(config) = &mut self.config {
+            config.promisc = promisc;
+        }
+        Ok(self)
+    }
+
+    /// int pcap_set_immediate_mode(pcap_t *p, int immediate_mode)
+    ///
+    /// Only possible before activation; use `reconfigure` to switch a live
+    /// capture.
+    pub fn set_immediate_mode(&mut self, immediate: bool) -> Result<&mut Self,Error> {
+        if self.activated {
+            return Err(Error::with_message(
+                ffi::PCAP_ERROR_ACTIVATED,
+                "immediate mode can only be changed before activation",
+            ));
+        }
+        self.chkerr(unsafe { ffi::pcap_set_immediate_mode(self.handle, immediate as i32) })?;
+        if let Some(config) = &mut self.config {
+            config.immediate_mode = immediate;
+        }
+        Ok(self)
+    }
+
+    /// Close the handle and open its device again with the settings `f`
+    /// makes to its `CaptureConfig`, then apply the filter it had, e.g. to
+    /// switch immediate mode on a running capture. Packets arriving in
+    /// between are missed, and if reopening fails the old handle is gone
+    /// regardless. Only for handles opened on a device.
+    pub fn reconfigure<F: FnOnce(&mut CaptureConfig)>(self, f: F) -> Result<Handle, Error> {
+        let mut config = match &self.config {
+            Some(config) => config.clone(),
+            None => return Err(Error::with_message(ffi::PCAP_ERROR, "only live captures can be reopened")),
+        };
+        f(&mut config);
+        let filter = self.filter.borrow_mut().take();
+        drop(self);
+        let handle = config.open()?;
+        if let Some(filter) = filter {
+            handle.set_filter(&filter)?;
+        }
+        Ok(handle)
+    }
+
+    /// `set_nonblock(true)`, or on backends that don't support it, the
+    /// closest approximation available: a 1 ms read timeout, which can only
+    /// be set before activation. A 0 ms timeout would mean waiting forever on
+    /// some platforms.
+    ///
+    /// Fails if neither works, i.e. non-blocking mode is unsupported and the
+    /// handle is already activated; reads then have to happen on a thread of
+    /// their own.
+    pub fn set_nonblock_or_fallback(&mut self) -> Result<NonblockStrategy,Error> {
+        const FALLBACK_TIMEOUT: Duration = Duration::from_millis(1);
+
+        let err = match self.set_nonblock(true) {
+            Ok(_) => return Ok(NonblockStrategy::NonBlocking),
+            Err(err) => err,
+        };
+        if self.activated {
+            return Err(Error::with_message(err.code(), &format!(
+                "non-blocking mode isn't supported ({}) and the read timeout can't be changed after activation",
+                err.message().unwrap_or("no reason given")
+            )));
+        }
+        self.set_timeout(FALLBACK_TIMEOUT)?;
+        Ok(NonblockStrategy::ShortTimeout(FALLBACK_TIMEOUT))
+    }
+
+    /// Whether the device this handle captures on is in promiscuous mode,
+    /// read from the kernel's interface flags after activation. This is the
+    /// interface's state, not just this handle's request: it is also true if
+    /// another process or capture put the interface into promiscuous mode.
+    ///
+    /// Fails for savefiles and the `any` device, which have no single
+    /// interface to ask about.
+    #[cfg(target_os = "linux")]
+    pub fn promisc_active(&self) -> Result<bool, Error> {
+        let device = match &self.config {
+            Some(config) if config.device != ANY_DEVICE => &config.device,
+            _ => return Err(Error::with_message(ffi::PCAP_ERROR, "handle has no single device")),
+        };
+        let flags = std::fs::read_to_string(format!("/sys/class/net/{}/flags", device))?;
+        Ok(promisc_from_sysfs(&flags))
+    }
+
+    /// int pcap_set_buffer_size(pcap_t *p, int buffer_size)
+    ///
+    /// See `max_buffer_size` for the system's limit.
+    pub fn set_buffer_size(&mut self, buffer_size: i32) -> Result<&mut Self,Error> {
+        self.chkerr(unsafe { ffi::pcap_set_buffer_size(self.handle, buffer_size) })?;
+        Ok(self)
+    }
+
+    /// Size the Linux capture ring as `count` blocks of `ring_block_size`
+    /// bytes, through `set_buffer_size`; the two override each other, so
+    /// call only one. Like `set_buffer_size`, only before activation.
+    #[cfg(target_os = "linux")]
+    pub fn set_ring_block_count(&mut self, count: u32) -> Result<&mut Self,Error> {
+        let buffer_size = ring_block_size()
+            .checked_mul(count as usize)
+            .filter(|&size| size > 0 && size <= i32::MAX as usize)
+            .ok_or_else(|| Error::with_message(ffi::PCAP_ERROR, &format!("can't allocate {} ring blocks", count)))?;
+        self.set_buffer_size(buffer_size as i32)
+    }
+
+    /// int pcap_set_timeout(pcap_t *p, int to_ms)
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<&mut Self,Error> {
+        let read_timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+        self.chkerr(unsafe {
+            ffi::pcap_set_timeout(
+                self.handle,
+                read_timeout_ms
+            )
+        })?;
+        if let Some(config) = &mut self.config {
+            config.read_timeout_ms = read_timeout_ms;
+        }
+        self.timeout = Some(timeout);
+        Ok(self)
+    }
+
+    /// FILE *pcap_file(pcap_t *p)
+    ///
+    /// Whether this handle reads a savefile
+    pub fn is_savefile(&self) -> bool {
+        !unsafe { ffi::pcap_file(self.handle) }.is_null()
+    }
+
+    /// A multi-line, human-readable report of everything this handle can
+    /// tell about itself, for pasting into bug reports. Values libpcap
+    /// refuses to give (e.g. before activation) are shown as unavailable.
+    pub fn describe(&self) -> String {
+        fn or_unavailable<T: fmt::Display>(value: Result<T,Error>) -> String {
+            value.map_or_else(|err| format!("unavailable ({})", err), |value| value.to_string())
+        }
+
+        let mut report = String::new();
+        let source = match &self.config {
+            _ if self.is_savefile() => "savefile".to_owned(),
+            Some(config) => format!("live capture on {}", config.device),
+            None => "dead handle".to_owned(),
+        };
+        let _ = writeln!(report, "source: {}", source);
+        let _ = writeln!(report, "link type: {:?} ({})", self.datalink_type(), self.datalink());
+        let _ = writeln!(report, "snaplen: {}", or_unavailable(self.snapshot()));
+        if let Some(config) = &self.config {
+            let _ = writeln!(report, "promiscuous: {}", config.promisc);
+        }
+        let _ = writeln!(report, "non-blocking: {}", or_unavailable(self.get_nonblock()));
+        let _ = match self.timeout {
+            Some(timeout) => writeln!(report, "timeout: {:?}", timeout),
+            None => writeln!(report, "timeout: platform default"),
+        };
+        let _ = writeln!(report, "timestamp precision: {:?}", self.tstamp_precision());
+        if self.is_savefile() {
+            let (major, minor) = self.version();
+            let _ = writeln!(report, "savefile version: {}.{}", major, minor);
+            let _ = writeln!(report, "byte-swapped: {}", or_unavailable(self.is_swapped()));
+        } else if self.config.is_some() {
+            let stats = self.stats().map(|stats| stats.to_string());
+            let _ = writeln!(report, "stats: {}", or_unavailable(stats));
+        }
+        report
+    }
+
+    /// Whether to log packets that weren't captured in full. On by default;
+    /// turn it off when truncation is expected and the log noise isn't
+    /// wanted. Clamping of impossible `caplen` values is always reported.
+    pub fn set_truncation_warnings(&mut self, enabled: bool) -> &mut Self {
+        self.truncation_warnings = enabled;
+        self
+    }
+
+    /// The read timeout last given to `open_live` or `set_timeout`.
+    ///
+    /// libpcap has no way to query it, so this is `None` if it was never set
+    /// explicitly and the platform default is in effect.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Whether the handle is ready to capture. Only a handle from `create`
+    /// starts out inactive; every other way of opening one activates it.
+    pub fn is_activated(&self) -> bool {
+        self.activated
+    }
+
+    /// Positive return values from `pcap_activate` are warnings; they are
+    /// logged and the handle is still usable. If the warning is that
+    /// promiscuous mode isn't supported, the recorded `CaptureConfig` is
+    /// updated to say it is off.
+    ///
+    /// Activating a handle a second time fails with
+    /// `ErrorKind::AlreadyActivated` without calling into libpcap.
+    pub fn activate(&mut self) -> Result<(),Error> {
+        if self.activated {
+            return Err(Error::with_message(ffi::PCAP_ERROR_ACTIVATED, "handle is already activated"));
+        }
+        match unsafe { ffi::pcap_activate(self.handle) } {
+            0 => {}
+            rc if rc > 0 => {
+                warn!("{}", Error::from_last(self.handle, rc));
+                if rc == ffi::PCAP_WARNING_PROMISC_NOTSUP as i32 {
+                    if let Some(config) = &mut self.config {
+                        config.promisc = false;
+                    }
+                }
+            }
+            rc => return Err(Error::from_last(self.handle, rc)),
+        }
+        self.activated = true;
+        Ok(())
+    }
+}
+
+#[cfg(feature="breakable")]
+impl LoopBreaker {
+    /// See `Handle::break_loop`, including why breaking when no loop is
+    /// running leaves the request pending for the next one.
+    pub fn break_loop(&self) {
+        unsafe { ffi::pcap_breakloop(self.handle) }
+    }
+
+    /// Whether a `loop_` or `dispatch` is running on the handle, i.e. a
+    /// `break_loop` now would stop it rather than the next one. Always false
+    /// once the `Handle` has been dropped.
+    ///
+    /// The flag is set before libpcap is entered and cleared after it
+    /// returns, so `false` reliably means no loop was running when it was
+    /// read. `true` can be stale: the loop may finish on its own between
+    /// this returning and `break_loop` taking effect, and libpcap offers no
+    /// way to close that window. Shutdown code that can't tolerate one empty
+    /// run of the next loop should also tell the capture thread to stop by
+    /// other means.
+    pub fn is_active(&self) -> bool {
+        self._handle_lifetime.looping.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(feature="breakable")]
+impl Drop for HandleLifetime {
+    fn drop(&mut self) {
+        unsafe { ffi::pcap_close(self.handle) }
+    }
 }
 
 #[cfg(not(feature="breakable"))]
@@ -522,15 +1906,522 @@ impl Drop for Handle {
     }
 }
 
+#[cfg(target_os = "linux")]
+fn sendmmsg_all(fd: libc::c_int, frames: &[&[u8]]) -> Result<usize, Error> {
+    // UIO_MAXIOV, the most the kernel takes in one call
+    const MAX_BATCH: usize = 1024;
+
+    let mut iovecs: Vec<libc::iovec> = frames
+        .iter()
+        .map(|frame| libc::iovec {
+            iov_base: frame.as_ptr() as *mut libc::c_void,
+            iov_len: frame.len(),
+        })
+        .collect();
+    let mut msgs: Vec<libc::mmsghdr> = iovecs
+        .iter_mut()
+        .map(|iov| {
+            let mut msg: libc::mmsghdr = unsafe { std::mem::zeroed() };
+            msg.msg_hdr.msg_iov = iov;
+            msg.msg_hdr.msg_iovlen = 1;
+            msg
+        })
+        .collect();
+
+    let mut sent = 0;
+    while sent < msgs.len() {
+        let batch = (msgs.len() - sent).min(MAX_BATCH);
+        let rc = unsafe { libc::sendmmsg(fd, msgs[sent..].as_mut_ptr(), batch as libc::c_uint, 0) };
+        if rc < 0 {
+            if sent == 0 {
+                let err = std::io::Error::last_os_error();
+                return Err(Error::with_message(ffi::PCAP_ERROR, &format!("send: {}", err)));
+            }
+            break;
+        }
+        if rc == 0 {
+            break;
+        }
+        sent += rc as usize;
+    }
+    Ok(sent)
+}
+
+/// One filter compiled both ways, see `Handle::compile_both`
+pub struct CompiledFilters {
+    pub optimized: BpfProgram,
+    pub unoptimized: BpfProgram,
+}
+
+/// One BPF instruction, as printed by `tcpdump -dd`
+pub type BpfInsn = ffi::bpf_insn;
+
+/// A compiled filter, created with `Handle::compile` and freed on drop
+pub struct BpfProgram {
+    program: ffi::bpf_program,
+    /// the instructions `program` points to if they were supplied by the
+    /// caller rather than allocated by libpcap
+    owned: Option<Box<[BpfInsn]>>,
+}
+
+// The program is a heap buffer of instructions, allocated either by libpcap
+// in pcap_compile or by `from_instructions`, that nothing else references: pcap_setfilter copies it
+// into the handle. Owning the only pointer, it can be freed on any thread.
+// It stays !Sync; nothing about concurrent use of one program has been
+// audited, and sharing isn't needed since applying it doesn't consume it.
+unsafe impl Send for BpfProgram{}
+
+impl BpfProgram {
+    fn compiled(program: ffi::bpf_program) -> BpfProgram {
+        BpfProgram { program, owned: None }
+    }
+
+    /// A program from already compiled instructions, e.g. the output of
+    /// `tcpdump -dd`, skipping `pcap_compile`. The instructions aren't
+    /// checked here; the kernel or libpcap validates them in `set_filter`.
+    pub fn from_instructions(insns: &[BpfInsn]) -> BpfProgram {
+        let mut owned: Box<[BpfInsn]> = insns.into();
+        BpfProgram {
+            program: ffi::bpf_program {
+                bf_len: owned.len() as _,
+                bf_insns: owned.as_mut_ptr(),
+            },
+            owned: Some(owned),
+        }
+    }
+
+    pub fn instructions(&self) -> &[BpfInsn] {
+        if self.program.bf_insns.is_null() {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.program.bf_insns, self.len()) }
+    }
+
+    /// int pcap_offline_filter(const struct bpf_program *fp, const struct pcap_pkthdr *h, const u_char *pkt)
+    ///
+    /// Whether the filter accepts a packet, without a handle to apply it to
+    pub fn matches(&self, header: &PacketHeader, data: &[u8]) -> bool {
+        let pkthdr = raw_header(header, data);
+        unsafe { ffi::pcap_offline_filter(&self.program, &pkthdr, data.as_ptr()) != 0 }
+    }
+
+    /// Whether both programs consist of the same instructions. Filters that
+    /// match the same packets can still compile differently, so this can
+    /// only show that a rewritten filter is unchanged, not that it isn't.
+    pub fn bytecode_eq(&self, other: &BpfProgram) -> bool {
+        let same = |a: &BpfInsn, b: &BpfInsn| (a.code, a.jt, a.jf, a.k) == (b.code, b.jt, b.jf, b.k);
+        self.len() == other.len() && self.instructions().iter().zip(other.instructions()).all(|(a, b)| same(a, b))
+    }
+
+    /// Number of BPF instructions
+    pub fn len(&self) -> usize {
+        self.program.bf_len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.program.bf_len == 0
+    }
+}
+
+impl Drop for BpfProgram {
+    fn drop(&mut self) {
+        // owned instructions are freed with the Box
+        if self.owned.is_none() {
+            unsafe { ffi::pcap_freecode(&mut self.program) }
+        }
+    }
+}
+
+/// int pcap_compile_nopcap(int snaplen, int linktype, struct bpf_program *fp, char *str, int optimize, bpf_u_int32 netmask)
+///
+/// Compile without a handle, for any handle with this link type and snaplen.
+/// libpcap doesn't say why compilation failed, so the error has no message.
+pub fn compile_nopcap(
+    snaplen: i32,
+    linktype: DataLinkType,
+    filter: &str,
+    optimize: bool,
+    netmask: u32,
+) -> Result<BpfProgram, Error> {
+    let filter = CString::new(filter)
+        .map_err(|_| Error::with_message(ffi::PCAP_ERROR, "filter contains a nul byte"))?;
+    let mut bpf_program = MaybeUninit::<ffi::bpf_program>::uninit();
+    let res = unsafe {
+        ffi::pcap_compile_nopcap(
+            snaplen,
+            linktype.as_raw(),
+            bpf_program.as_mut_ptr(),
+            filter.as_ptr(),
+            optimize as i32,
+            netmask,
+        )
+    };
+    if res != 0 {
+        return Err(Error { message: None, code: res });
+    }
+    Ok(BpfProgram::compiled(unsafe { bpf_program.assume_init() }))
+}
+
+/// Compiled filters, reused for every request with the same filter text,
+/// link type, snaplen and netmask.
+///
+/// `set_filter` copies the program into the handle, so one compiled program
+/// can be applied to any number of handles; batch jobs over many savefiles
+/// with the same link type then compile each filter once.
+#[derive(Default)]
+pub struct FilterCache {
+    programs: HashMap<(String, DataLinkType, i32, u32), Rc<BpfProgram>>,
+}
+
+impl FilterCache {
+    pub fn new() -> FilterCache {
+        FilterCache::default()
+    }
+
+    /// The optimized program for `filter`, compiled on first use
+    pub fn get(
+        &mut self,
+        filter: &str,
+        linktype: DataLinkType,
+        snaplen: i32,
+        netmask: u32,
+    ) -> Result<Rc<BpfProgram>, Error> {
+        let key = (filter.to_owned(), linktype, snaplen, netmask);
+        if let Some(program) = self.programs.get(&key) {
+            return Ok(program.clone());
+        }
+        let program = Rc::new(compile_nopcap(snaplen, linktype, filter, true, netmask)?);
+        self.programs.insert(key, program.clone());
+        Ok(program)
+    }
+
+    /// Compile `filter` for `handle`'s link type and snaplen, or reuse an
+    /// earlier compilation, and apply it
+    pub fn set_filter(&mut self, handle: &Handle, filter: &str, netmask: u32) -> Result<(), Error> {
+        let program = self.get(filter, handle.datalink_type(), handle.snapshot()?, netmask)?;
+        handle.set_filter(&program)
+    }
+
+    pub fn len(&self) -> usize {
+        self.programs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.programs.clear()
+    }
+}
+
+/// `header` as libpcap's `pcap_pkthdr`, with `caplen` limited to `data`
+fn raw_header(header: &PacketHeader, data: &[u8]) -> ffi::pcap_pkthdr {
+    let mut pkthdr = unsafe { MaybeUninit::<ffi::pcap_pkthdr>::zeroed().assume_init() };
+    pkthdr.ts.tv_sec = header.ts.sec as _;
+    pkthdr.ts.tv_usec = header.ts.usec as _;
+    pkthdr.caplen = header.caplen.min(data.len() as u32);
+    pkthdr.len = header.len;
+    pkthdr
+}
+
+/// A savefile being written by libpcap, created with `Handle::dump_open`
+pub struct Dumper {
+    dumper: *mut ffi::pcap_dumper,
+    precision: TstampPrecision,
+}
+
+unsafe impl Send for Dumper{}
+
+impl Dumper {
+    /// void pcap_dump(u_char *user, struct pcap_pkthdr *h, u_char *sp)
+    ///
+    /// At most `data.len()` bytes are written, whatever `header.caplen` says.
+    pub fn write(&mut self, header: &PacketHeader, data: &[u8]) {
+        let pkthdr = raw_header(header, data);
+        unsafe { ffi::pcap_dump(self.dumper as *mut libc::c_uchar, &pkthdr, data.as_ptr()) }
+    }
+
+    /// Write `data` as a complete packet stamped with the current system time
+    /// at the precision of the handle the dumper was opened from.
+    pub fn write_now(&mut self, data: &[u8]) {
+        let header = PacketHeader {
+            ts: TimeStamp::from_system_time(SystemTime::now(), self.precision)
+                .unwrap_or(TimeStamp { sec: 0, usec: 0 }),
+            caplen: data.len() as u32,
+            len: data.len() as u32,
+        };
+        self.write(&header, data)
+    }
+
+    /// int pcap_dump_flush(pcap_dumper_t *p)
+    pub fn flush(&mut self) -> Result<(),Error> {
+        match unsafe { ffi::pcap_dump_flush(self.dumper) } {
+            0 => Ok(()),
+            rc => Err(Error::with_message(rc, "failed to flush savefile")),
+        }
+    }
+}
+
+impl Drop for Dumper {
+    fn drop(&mut self) {
+        unsafe { ffi::pcap_dump_close(self.dumper) }
+    }
+}
+
+#[cfg(unix)]
+fn path_to_cstring(path: &Path) -> Result<CString, Error> {
+    use std::os::unix::ffi::OsStrExt;
+    CString::new(path.as_os_str().as_bytes())
+        .map_err(|_| Error::with_message(ffi::PCAP_ERROR, "path contains a nul byte"))
+}
+
+#[cfg(windows)]
+fn path_to_cstring(path: &Path) -> Result<CString, Error> {
+    path.to_str()
+        .and_then(|path| CString::new(path).ok())
+        .ok_or_else(|| Error::with_message(ffi::PCAP_ERROR, "path is not representable as a C string"))
+}
+
+/// A clearer error for a file libpcap failed to open, if that's because it's
+/// pcapng
+fn unsupported_format(path: &Path) -> Option<Error> {
+    let mut start = [0u8; 4];
+    File::open(path).ok()?.read_exact(&mut start).ok()?;
+    if !pcapng::is_pcapng(&start) {
+        return None;
+    }
+    Some(Error::with_message(
+        ERROR_UNSUPPORTED_FORMAT,
+        &format!(
+            "{} is a pcapng file, which the linked libpcap can't read; see pcapng::PcapNgReader",
+            path.display()
+        ),
+    ))
+}
+
+/// pcap_t *pcap_open_offline(const char *fname, char *errbuf)
+pub fn open_offline<P: AsRef<Path>>(path: P) -> Result<Handle, Error> {
+    open_offline_with_tstamp_precision(path, TstampPrecision::Micro)
+}
+
+/// pcap_t *pcap_open_offline_with_tstamp_precision(const char *fname, u_int precision, char *errbuf)
+///
+/// Timestamps are converted to `precision` whatever the file was written with.
+///
+/// libpcap before 1.1 can't read pcapng files; opening one with it fails
+/// with `ErrorKind::UnsupportedFormat` rather than libpcap's "unknown file
+/// format".
+pub fn open_offline_with_tstamp_precision<P: AsRef<Path>>(
+    path: P,
+    precision: TstampPrecision,
+) -> Result<Handle, Error> {
+    let path = path.as_ref();
+    let c_path = path_to_cstring(path)?;
+    let mut err_buf = ErrBuf::new();
+    let handle = unsafe {
+        ffi::pcap_open_offline_with_tstamp_precision(c_path.as_ptr(), precision.as_raw() as _, err_buf.as_raw_ptr())
+    };
+    if handle.is_null() {
+        Err(unsupported_format(path).unwrap_or_else(|| Error::new(err_buf, ffi::PCAP_ERROR)))
+    } else {
+        Ok(Handle::new(handle))
+    }
+}
+
+/// pcap_t *pcap_open_dead(int linktype, int snaplen)
+///
+/// A handle not attached to any device, for compiling filters and writing
+/// savefiles.
+pub fn open_dead(linktype: i32, snaplen: i32) -> Result<Handle, Error> {
+    open_dead_with_tstamp_precision(linktype, snaplen, TstampPrecision::Micro)
+}
+
+/// pcap_t *pcap_open_dead_with_tstamp_precision(int linktype, int snaplen, u_int precision)
+pub fn open_dead_with_tstamp_precision(
+    linktype: i32,
+    snaplen: i32,
+    precision: TstampPrecision,
+) -> Result<Handle, Error> {
+    let handle = unsafe {
+        ffi::pcap_open_dead_with_tstamp_precision(linktype, snaplen, precision.as_raw() as _)
+    };
+    if handle.is_null() {
+        Err(Error::with_message(ffi::PCAP_ERROR, "pcap_open_dead failed"))
+    } else {
+        Ok(Handle::new(handle))
+    }
+}
+
 pub fn create(interface_name: &str) -> Result<Handle, Error> {
     let interface_name = CString::new(interface_name).unwrap();
     let mut err_buf = ErrBuf::new();
     let handle = unsafe { ffi::pcap_create(interface_name.as_ptr(), err_buf.as_raw_ptr()) };
     if handle.is_null() {
-        Err(Error::new(err_buf, 1))
+        Err(Error::new(err_buf, ffi::PCAP_ERROR))
     } else {
-        Ok(Handle::new(handle))
+        let mut handle = Handle::with_config(handle, CaptureConfig::new(&interface_name.to_string_lossy()));
+        handle.activated = false;
+        Ok(handle)
+    }
+}
+
+/// What a source string (see `make_source`) refers to
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum SourceKind {
+    /// a savefile, `file://<path>`
+    File,
+    /// a local interface, `rpcap://<name>`
+    Local,
+    /// an interface on a host running rpcapd, `rpcap://<host>:<port>/<name>`
+    Remote,
+}
+
+impl SourceKind {
+    fn as_raw(self) -> i32 {
+        (match self {
+            SourceKind::File => ffi::PCAP_SRC_FILE,
+            SourceKind::Local => ffi::PCAP_SRC_IFLOCAL,
+            SourceKind::Remote => ffi::PCAP_SRC_IFREMOTE,
+        }) as i32
+    }
+
+    fn from_raw(raw: i32) -> Option<SourceKind> {
+        match raw as u32 {
+            ffi::PCAP_SRC_FILE => Some(SourceKind::File),
+            ffi::PCAP_SRC_IFLOCAL => Some(SourceKind::Local),
+            ffi::PCAP_SRC_IFREMOTE => Some(SourceKind::Remote),
+            _ => None,
+        }
+    }
+}
+
+/// A source string split up by `parse_source`; parts the string doesn't
+/// have are `None`
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct ParsedSource {
+    pub kind: SourceKind,
+    pub host: Option<String>,
+    pub port: Option<String>,
+    /// the interface name or savefile path
+    pub name: Option<String>,
+}
+
+type SourceBuf = [libc::c_char; ffi::PCAP_BUF_SIZE as usize];
+
+fn source_part(buf: &SourceBuf) -> Option<String> {
+    error_message(unsafe { CStr::from_ptr(buf.as_ptr()) })
+}
+
+fn source_cstring(value: &str) -> Result<CString, Error> {
+    CString::new(value).map_err(|_| Error::with_message(ffi::PCAP_ERROR, "source string part contains a nul byte"))
+}
+
+/// int pcap_createsrcstr(char *source, int type, const char *host, const char *port, const char *name, char *errbuf)
+///
+/// Build the source string for a savefile or interface, as taken by the
+/// remote-capable `pcap_open`. `host` and `port` only matter for
+/// `SourceKind::Remote`, where the port defaults to rpcapd's. Needs
+/// libpcap 1.9 or later, or WinPcap/Npcap.
+pub fn make_source(kind: SourceKind, host: Option<&str>, port: Option<&str>, name: &str) -> Result<String, Error> {
+    let host = host.map(source_cstring).transpose()?;
+    let port = port.map(source_cstring).transpose()?;
+    let name = source_cstring(name)?;
+    let mut source: SourceBuf = [0; ffi::PCAP_BUF_SIZE as usize];
+    let mut err_buf = ErrBuf::new();
+    let rc = unsafe {
+        ffi::pcap_createsrcstr(
+            source.as_mut_ptr(),
+            kind.as_raw(),
+            host.as_ref().map_or(ptr::null(), |host| host.as_ptr()),
+            port.as_ref().map_or(ptr::null(), |port| port.as_ptr()),
+            name.as_ptr(),
+            err_buf.as_raw_ptr(),
+        )
+    };
+    if rc != 0 {
+        return Err(Error::new(err_buf, ffi::PCAP_ERROR));
     }
+    Ok(source_part(&source).unwrap_or_default())
+}
+
+/// int pcap_parsesrcstr(const char *source, int *type, char *host, char *port, char *name, char *errbuf)
+///
+/// Split a source string into its parts; the inverse of `make_source`. A
+/// plain interface name without a scheme is a `SourceKind::Local` source.
+pub fn parse_source(source: &str) -> Result<ParsedSource, Error> {
+    let source = source_cstring(source)?;
+    let mut kind = 0;
+    let mut host: SourceBuf = [0; ffi::PCAP_BUF_SIZE as usize];
+    let mut port: SourceBuf = [0; ffi::PCAP_BUF_SIZE as usize];
+    let mut name: SourceBuf = [0; ffi::PCAP_BUF_SIZE as usize];
+    let mut err_buf = ErrBuf::new();
+    let rc = unsafe {
+        ffi::pcap_parsesrcstr(
+            source.as_ptr(),
+            &mut kind,
+            host.as_mut_ptr(),
+            port.as_mut_ptr(),
+            name.as_mut_ptr(),
+            err_buf.as_raw_ptr(),
+        )
+    };
+    if rc != 0 {
+        return Err(Error::new(err_buf, ffi::PCAP_ERROR));
+    }
+    let kind = SourceKind::from_raw(kind)
+        .ok_or_else(|| Error::with_message(ffi::PCAP_ERROR, &format!("unknown source type {}", kind)))?;
+    Ok(ParsedSource {
+        kind,
+        host: source_part(&host),
+        port: source_part(&port),
+        name: source_part(&name),
+    })
+}
+
+/// Open `interface_name` for sending packets only.
+///
+/// libpcap can only inject on an activated handle, and activating one always
+/// sets up capture. To keep that capture as cheap as possible the handle gets
+/// a small snaplen, is restricted to outgoing traffic where the platform
+/// supports it, and has a filter that rejects every packet, so nothing is
+/// ever copied to userspace. `send_packet`, `inject` and `send_batch` work as
+/// usual; capture calls simply never see a packet.
+pub fn open_transmit(interface_name: &str) -> Result<Handle, Error> {
+    const TRANSMIT_SNAPLEN: u32 = 64;
+
+    let mut handle = create(interface_name)?;
+    handle.set_snaplen(TRANSMIT_SNAPLEN)?;
+    handle.activate()?;
+    // the reject-all filter below is what really disables capture, so a
+    // platform without direction support is fine
+    let _ = handle.set_direction(Direction::Out);
+
+    let reject_all = handle.compile("less 0", true, ffi::PCAP_NETMASK_UNKNOWN)?;
+    handle.set_filter(&reject_all)?;
+    Ok(handle)
+}
+
+/// Open `count` handles on `config.device` sharing one `PACKET_FANOUT`
+/// group, one per capture thread. Each handle sees its own share of the
+/// traffic as decided by `mode`; see `Handle::set_fanout`.
+///
+/// `group_id` must not be in use by another process unless sharing with it
+/// is intended.
+#[cfg(target_os = "linux")]
+pub fn open_fanout(
+    config: &CaptureConfig,
+    count: usize,
+    group_id: u16,
+    mode: FanoutMode,
+) -> Result<Vec<Handle>, Error> {
+    (0..count)
+        .map(|_| {
+            let mut handle = config.open()?;
+            handle.set_fanout(group_id, mode, false)?;
+            Ok(handle)
+        })
+        .collect()
 }
 
 pub fn open_live(
@@ -539,6 +2430,14 @@ pub fn open_live(
     promisc: bool,
     read_timeout_ms: i32,
 ) -> Result<Handle, Error> {
+    let config = CaptureConfig {
+        device: interface_name.to_owned(),
+        snaplen,
+        promisc,
+        read_timeout_ms,
+        immediate_mode: false,
+        exclusive: false,
+    };
     let interface_name = CString::new(interface_name).unwrap();
     let mut err_buf = ErrBuf::new();
     let handle = unsafe {
@@ -551,10 +2450,34 @@ pub fn open_live(
         )
     };
     if handle.is_null() {
-        Err(Error::new(err_buf, 0))
+        Err(Error::new(err_buf, ffi::PCAP_ERROR))
     } else {
-        Ok(Handle::new(handle))
+        let mut handle = Handle::with_config(handle, config);
+        handle.timeout = Some(Duration::from_millis(read_timeout_ms.max(0) as u64));
+        if handle.datalink_type().is_cooked() {
+            warn!(
+                "{} captures with {:?} headers, not Ethernet; see open_any",
+                handle.config.as_ref().unwrap().device,
+                handle.datalink_type()
+            );
+        }
+        Ok(handle)
+    }
+}
+
+/// Capture on every interface through the Linux `any` pseudo-device.
+///
+/// Packets start with a cooked-capture header (`DataLinkType::LinuxSll`, or
+/// `LinuxSll2` if selected with `set_datalink`) instead of the interfaces'
+/// own link layers, and promiscuous mode isn't supported. Check
+/// `datalink_type` before parsing.
+pub fn open_any(snaplen: i32, read_timeout_ms: i32) -> Result<Handle, Error> {
+    CaptureConfig {
+        snaplen,
+        read_timeout_ms,
+        ..CaptureConfig::new(ANY_DEVICE)
     }
+    .open()
 }
 
 pub fn test() {
@@ -566,9 +2489,301 @@ pub fn test() {
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use savefile::{PcapReader, PcapWriter};
+    use std::fs::File;
+    use testutil::TempFile;
+
     #[test]
     fn it_works() {
         super::test();
         assert_eq!(2 + 2, 4);
     }
+
+    #[cfg(unix)]
+    #[test]
+    #[ignore = "needs a real libpcap to write the savefile"]
+    fn dumps_to_an_open_file() {
+        let file = TempFile::new("dump-fd.pcap");
+        let handle = open_dead(DataLinkType::Raw.as_raw(), 65535).unwrap();
+        let mut dumper = handle.dump_open_fd(File::create(file.path()).unwrap()).unwrap();
+        let header = PacketHeader { ts: TimeStamp { sec: 1, usec: 2 }, caplen: 3, len: 3 };
+        dumper.write(&header, &[1, 2, 3]);
+        drop(dumper);
+
+        let mut reader = PcapReader::new(File::open(file.path()).unwrap()).unwrap();
+        let (read, data) = reader.next_packet().unwrap().unwrap();
+        assert_eq!((read.ts, read.caplen, data), (header.ts, 3, vec![1, 2, 3]));
+    }
+
+    #[test]
+    #[ignore = "needs a real libpcap"]
+    fn activating_twice_is_reported() {
+        let mut handle = open_dead(1, 65535).unwrap();
+        assert!(handle.is_activated());
+        assert_eq!(handle.activate().unwrap_err().kind(), ErrorKind::AlreadyActivated);
+        assert_eq!(handle.set_promisc(true).err().map(|err| err.kind()), Some(ErrorKind::AlreadyActivated));
+        assert_eq!(handle.set_immediate_mode(true).err().map(|err| err.kind()), Some(ErrorKind::AlreadyActivated));
+        // not opened on a device, so there is nothing to reopen
+        assert!(handle.reconfigure(|config| config.immediate_mode = true).is_err());
+    }
+
+    #[test]
+    #[ignore = "needs a real libpcap and permission to capture on loopback"]
+    fn reconfigure_keeps_the_device_and_filter() {
+        let handle = CaptureConfig::new("lo").open().unwrap();
+        let filter = handle.compile("udp", true, 0).unwrap();
+        handle.set_filter(&filter).unwrap();
+        let handle = handle.reconfigure(|config| config.immediate_mode = true).unwrap();
+        let config = handle.config().unwrap();
+        assert_eq!((config.device.as_str(), config.immediate_mode), ("lo", true));
+        assert!(handle.filter.borrow().as_ref().unwrap().bytecode_eq(&filter));
+    }
+
+    #[test]
+    #[ignore = "needs a real libpcap and permission to capture on loopback"]
+    fn snapshot_reports_the_effective_snaplen() {
+        let mut handle = create("lo").unwrap();
+        handle.set_snaplen(0).unwrap();
+        assert_eq!(handle.snapshot().unwrap_err().kind(), ErrorKind::NotActivated);
+        handle.activate().unwrap();
+        let snaplen = handle.snapshot().unwrap();
+        assert!(snaplen > 0 && snaplen as u32 <= MAX_SNAPLEN);
+    }
+
+    #[test]
+    fn programs_from_instructions() {
+        // tcpdump -dd "": accept everything
+        let accept_all = [BpfInsn { code: 0x06, jt: 0, jf: 0, k: 262_144 }];
+        let program = BpfProgram::from_instructions(&accept_all);
+        assert_eq!(program.len(), 1);
+        assert_eq!(program.instructions()[0].k, 262_144);
+
+        assert!(program.bytecode_eq(&BpfProgram::from_instructions(&accept_all)));
+        let reject_all = [BpfInsn { code: 0x06, jt: 0, jf: 0, k: 0 }];
+        assert!(!program.bytecode_eq(&BpfProgram::from_instructions(&reject_all)));
+        assert!(!program.bytecode_eq(&BpfProgram::from_instructions(&[])));
+    }
+
+    #[test]
+    #[ignore = "needs a real libpcap"]
+    fn optimizer_shortens_programs() {
+        let handle = open_dead(1, 65535).unwrap();
+        let both = handle.compile_both("tcp port 80 or tcp port 443", 0).unwrap();
+        assert!(both.optimized.len() < both.unoptimized.len());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn ring_blocks_are_page_aligned() {
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        assert_eq!(ring_block_size() % page_size, 0);
+        assert!(ring_block_size() >= MAX_SNAPLEN as usize);
+    }
+
+    #[test]
+    fn recognizes_pcapng_files() {
+        let file = TempFile::new("format.pcapng");
+        let path = file.path();
+        let mut dumper = pcapng::PcapNgDumper::new(File::create(path).unwrap(), 1, 65535);
+        dumper.flush().unwrap();
+        drop(dumper);
+        let err = unsupported_format(path).unwrap();
+        assert_eq!(err.kind(), ErrorKind::UnsupportedFormat);
+
+        let mut writer = PcapWriter::new(File::create(path).unwrap(), 1, 65535).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+        assert!(unsupported_format(path).is_none());
+    }
+
+    #[test]
+    fn unknown_link_type_names_are_rejected() {
+        let err = datalink_name_to_val("NOT_A_LINK_TYPE").unwrap_err();
+        assert!(err.message().unwrap().contains("NOT_A_LINK_TYPE"));
+        assert!(datalink_name_to_val("EN10MB\0").is_err());
+    }
+
+    #[test]
+    #[ignore = "needs a real libpcap to know the link type names"]
+    fn link_type_names_resolve() {
+        assert_eq!(datalink_name_to_val("en10mb").unwrap(), DataLinkType::En10Mb);
+    }
+
+    #[test]
+    #[ignore = "needs libpcap 1.9 or later"]
+    fn source_strings_round_trip() {
+        let source = make_source(SourceKind::Remote, Some("10.0.0.1"), Some("2002"), "eth0").unwrap();
+        assert_eq!(source, "rpcap://10.0.0.1:2002/eth0");
+        let parsed = parse_source(&source).unwrap();
+        assert_eq!(parsed.kind, SourceKind::Remote);
+        assert_eq!(parsed.host.as_deref(), Some("10.0.0.1"));
+        assert_eq!(parsed.port.as_deref(), Some("2002"));
+        assert_eq!(parsed.name.as_deref(), Some("eth0"));
+    }
+
+    #[test]
+    fn error_messages_tolerate_invalid_utf8() {
+        let msg = CStr::from_bytes_with_nul(b"can't open \xff\xfe\0").unwrap();
+        assert_eq!(error_message(msg).unwrap(), "can't open \u{fffd}\u{fffd}");
+        assert_eq!(error_message(CStr::from_bytes_with_nul(b"\0").unwrap()), None);
+
+        let mut err_buf = ErrBuf::new();
+        for (dst, &src) in err_buf.buf.iter_mut().zip(b"no such device\0junk") {
+            *dst = src as i8;
+        }
+        assert_eq!(err_buf.read().unwrap(), "no such device");
+    }
+
+    #[test]
+    fn convert_packet_clamps_caplen() {
+        let data = [0u8; 8];
+        let mut pkthdr = unsafe { MaybeUninit::<ffi::pcap_pkthdr>::zeroed().assume_init() };
+        pkthdr.caplen = 1000;
+        pkthdr.len = 6;
+
+        let (header, packet) = unsafe { convert_packet(&pkthdr, data.as_ptr(), Some(8), true) };
+        assert_eq!((header.caplen, header.len, packet.len()), (6, 6, 6));
+
+        pkthdr.len = 1000;
+        let (header, packet) = unsafe { convert_packet(&pkthdr, data.as_ptr(), Some(4), true) };
+        assert_eq!((header.caplen, packet.len()), (4, 4));
+    }
+
+    #[test]
+    fn empty_packets_through_the_callback() {
+        let mut pkthdr = unsafe { MaybeUninit::<ffi::pcap_pkthdr>::zeroed().assume_init() };
+        pkthdr.len = 60;
+        let mut seen = vec![];
+        let mut callback = PacketCallback::new(ptr::null_mut(), |header, packet| {
+            let (header, packet) = unsafe { convert_packet(header, packet, Some(65535), false) };
+            seen.push((header.caplen, header.len, packet.len()));
+        });
+        let (handler, user_data) = callback.as_raw();
+        let handler = handler.unwrap();
+        let data = [0u8; 4];
+        unsafe {
+            handler(user_data, &pkthdr, ptr::null());
+            handler(user_data, &pkthdr, data.as_ptr());
+            pkthdr.caplen = 4;
+            handler(user_data, &pkthdr, ptr::null());
+        }
+        callback.finish();
+        assert_eq!(seen, [(0, 60, 0), (0, 60, 0), (0, 60, 0)]);
+    }
+
+    #[test]
+    fn filters_addresses_by_family() {
+        let address = |addr: &str| Address {
+            address: Some(addr.parse().unwrap()),
+            netmask: None,
+            broadcast: None,
+            destination: None,
+        };
+        let interface = NetworkInterface::new(
+            "eth0",
+            None,
+            vec![address("[fe80::1]:0"), address("10.0.0.1:0")],
+            IfFlags::empty(),
+        );
+        assert_eq!(interface.ipv4_addresses().collect::<Vec<_>>(), [Ipv4Addr::new(10, 0, 0, 1)]);
+        assert_eq!(interface.ipv6_addresses().count(), 1);
+        assert!(interface.has_ipv4() && interface.has_ipv6());
+    }
+
+    #[test]
+    fn tracks_devices_in_use() {
+        let device = "rustcap-test-in-use";
+        let first = DeviceRegistration::new(device);
+        let second = DeviceRegistration::new(device);
+        assert!(device_in_use(device));
+        let exclusive = CaptureConfig { exclusive: true, ..CaptureConfig::new(device) };
+        assert!(exclusive.open().err().and_then(|err| err.message().map(str::to_owned)).unwrap().contains(device));
+        drop(first);
+        assert!(device_in_use(device));
+        drop(second);
+        assert!(!device_in_use(device));
+    }
+
+    #[test]
+    fn friendly_name_falls_back_to_the_name() {
+        let interface = |description| NetworkInterface::new("eth0", description, vec![], IfFlags::empty());
+        assert_eq!(interface(Some("Intel(R) Ethernet Connection")).friendly_name(), "Intel(R) Ethernet Connection");
+        assert_eq!(interface(Some("")).friendly_name(), "eth0");
+        assert_eq!(interface(None).friendly_name(), "eth0");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn reads_sysfs_flags() {
+        assert_eq!(flags_from_sysfs("0x9\n", "unknown\n"), IfFlags::PCAP_IF_UP | IfFlags::PCAP_IF_LOOPBACK);
+        assert_eq!(flags_from_sysfs("0x1003\n", "up\n"), IfFlags::PCAP_IF_UP | IfFlags::PCAP_IF_RUNNING);
+        assert_eq!(flags_from_sysfs("0x1002\n", "down\n"), IfFlags::empty());
+        assert!(promisc_from_sysfs("0x1103\n"));
+        assert!(!promisc_from_sysfs("0x1003\n"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn pins_the_current_thread() {
+        std::thread::spawn(|| {
+            let cpu = unsafe { libc::sched_getcpu() };
+            assert!(cpu >= 0);
+            pin_current_thread(cpu as usize).unwrap();
+            assert!(pin_current_thread(libc::CPU_SETSIZE as usize).is_err());
+        }).join().unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parses_sysctl_values() {
+        assert_eq!(parse_sysctl("212992\n"), Some(212992));
+        assert_eq!(parse_sysctl("0\n"), None);
+        assert_eq!(parse_sysctl(""), None);
+    }
+
+    #[test]
+    fn parses_libpcap_versions() {
+        assert_eq!(parse_libpcap_version("libpcap version 1.10.4 (with TPACKET_V3)"), Some((1, 10)));
+        assert_eq!(
+            parse_libpcap_version("Npcap version 1.79, based on libpcap version 1.10.4"),
+            Some((1, 10))
+        );
+        assert_eq!(parse_libpcap_version("something else"), None);
+    }
+
+    #[test]
+    #[ignore = "needs a real libpcap to read the savefile"]
+    fn loop_header_copies_a_prefix() {
+        let packets = [&[1u8, 2, 3, 4, 5][..], &[6, 7]]
+            .map(|data| (PacketHeader { ts: TimeStamp { sec: 0, usec: 0 }, caplen: data.len() as u32, len: 60 }, data));
+        let file = TempFile::pcap("prefix.pcap", 1, packets);
+
+        let handle = open_offline(file.path()).unwrap();
+        let mut seen = vec![];
+        handle.loop_header(0, |_, buf: &[u8; 4], len| seen.push((*buf, len))).unwrap();
+        assert_eq!(seen, [([1, 2, 3, 4], 4), ([6, 7, 0, 0], 2)]);
+    }
+
+    #[test]
+    #[ignore = "needs a real libpcap to read the savefile"]
+    fn loop_runs_again_after_break() {
+        let packets = (0..3).map(|i| (PacketHeader { ts: TimeStamp { sec: i, usec: 0 }, caplen: 1, len: 1 }, [i as u8]));
+        let file = TempFile::pcap("break.pcap", 1, packets);
+
+        let mut handle = open_offline(file.path()).unwrap();
+        let mut seen = vec![];
+        handle.loop_(0, |_, data| {
+            seen.push(data[0]);
+            handle.break_loop();
+        }).unwrap();
+        handle.loop_(1, |_, data| seen.push(data[0])).unwrap();
+        match handle.next_packet().unwrap() {
+            NextResult::Packet(_, data) => seen.push(data[0]),
+            other => panic!("expected a packet, got {:?}", other),
+        }
+        assert!(matches!(handle.next_packet().unwrap(), NextResult::Broken));
+        assert_eq!(handle.packets_seen(), 3);
+        assert_eq!(seen, [0, 1, 2]);
+    }
 }
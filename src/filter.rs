@@ -0,0 +1,271 @@
+//! Building BPF filter expressions without string concatenation
+//!
+//! ```
+//! use rustcap::filter::Filter;
+//! use std::net::{IpAddr, Ipv4Addr};
+//!
+//! let filter = Filter::new()
+//!     .tcp()
+//!     .port(443)
+//!     .or()
+//!     .net(IpAddr::V4(Ipv4Addr::new(10, 1, 2, 3)), 8)
+//!     .build();
+//! assert_eq!(filter, "(tcp and port 443) or net 10.0.0.0/8");
+//! ```
+use std::fmt;
+use std::net::IpAddr;
+
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+enum Op {
+    And,
+    Or,
+}
+
+impl Op {
+    fn as_str(self) -> &'static str {
+        match self {
+            Op::And => "and",
+            Op::Or => "or",
+        }
+    }
+}
+
+/// Accumulates primitives left to right into a filter string for
+/// `Handle::compile`.
+///
+/// Adjacent primitives are joined with `and` unless `or()` is called between
+/// them. pcap-filter gives `and` and `or` the same precedence, so whenever
+/// the connective changes the expression built so far is parenthesized,
+/// making the grouping explicit. A trailing `and()`/`or()`/`negate()` with no
+/// primitive after it is ignored.
+#[derive(Clone,Debug,Default)]
+pub struct Filter {
+    expr: String,
+    last_op: Option<Op>,
+    pending_op: Option<Op>,
+    negate_next: bool,
+}
+
+impl Filter {
+    pub fn new() -> Filter {
+        Filter::default()
+    }
+
+    /// Join the next primitive with `and` (the default)
+    pub fn and(mut self) -> Filter {
+        self.pending_op = Some(Op::And);
+        self
+    }
+
+    /// Join the next primitive with `or`
+    pub fn or(mut self) -> Filter {
+        self.pending_op = Some(Op::Or);
+        self
+    }
+
+    /// Negate the next primitive
+    pub fn negate(mut self) -> Filter {
+        self.negate_next = !self.negate_next;
+        self
+    }
+
+    fn push(mut self, primitive: &str) -> Filter {
+        let primitive = if self.negate_next {
+            format!("not {}", primitive)
+        } else {
+            primitive.to_owned()
+        };
+        self.negate_next = false;
+
+        if self.expr.is_empty() {
+            self.expr = primitive;
+        } else {
+            let op = self.pending_op.unwrap_or(Op::And);
+            if matches!(self.last_op, Some(last) if last != op) {
+                self.expr = format!("({})", self.expr);
+            }
+            self.expr = format!("{} {} {}", self.expr, op.as_str(), primitive);
+            self.last_op = Some(op);
+        }
+        self.pending_op = None;
+        self
+    }
+
+    pub fn host(self, addr: IpAddr) -> Filter {
+        self.push(&format!("host {}", addr))
+    }
+
+    pub fn src_host(self, addr: IpAddr) -> Filter {
+        self.push(&format!("src host {}", addr))
+    }
+
+    pub fn dst_host(self, addr: IpAddr) -> Filter {
+        self.push(&format!("dst host {}", addr))
+    }
+
+    /// Matches any of `addrs`, as a single parenthesized primitive
+    pub fn any_host<I: IntoIterator<Item = IpAddr>>(self, addrs: I) -> Filter {
+        let hosts: Vec<String> = addrs.into_iter().map(|addr| format!("host {}", addr)).collect();
+        match hosts.len() {
+            0 => self,
+            1 => self.push(&hosts[0]),
+            _ => self.push(&format!("({})", hosts.join(" or "))),
+        }
+    }
+
+    /// The host bits of `addr` beyond `prefix_len` are cleared, since
+    /// libpcap rejects a `net` with them set.
+    pub fn net(self, addr: IpAddr, prefix_len: u8) -> Filter {
+        let network = match addr {
+            IpAddr::V4(addr) => {
+                let prefix_len = prefix_len.min(32);
+                let mask = u32::MAX.checked_shl(32 - u32::from(prefix_len)).unwrap_or(0);
+                IpAddr::from((u32::from(addr) & mask).to_be_bytes())
+            }
+            IpAddr::V6(addr) => {
+                let prefix_len = prefix_len.min(128);
+                let mask = u128::MAX.checked_shl(128 - u32::from(prefix_len)).unwrap_or(0);
+                IpAddr::from((u128::from(addr) & mask).to_be_bytes())
+            }
+        };
+        self.push(&format!("net {}/{}", network, prefix_len))
+    }
+
+    pub fn port(self, port: u16) -> Filter {
+        self.push(&format!("port {}", port))
+    }
+
+    pub fn src_port(self, port: u16) -> Filter {
+        self.push(&format!("src port {}", port))
+    }
+
+    pub fn dst_port(self, port: u16) -> Filter {
+        self.push(&format!("dst port {}", port))
+    }
+
+    /// Ports `lo` through `hi` inclusive, as `portrange`, which compiles to
+    /// a pair of comparisons however wide the range is; every libpcap this
+    /// crate links against supports it. A single port comes out as `port`.
+    pub fn port_range(self, lo: u16, hi: u16) -> Filter {
+        self.push(&range_primitive("", lo, hi))
+    }
+
+    pub fn src_port_range(self, lo: u16, hi: u16) -> Filter {
+        self.push(&range_primitive("src ", lo, hi))
+    }
+
+    pub fn dst_port_range(self, lo: u16, hi: u16) -> Filter {
+        self.push(&range_primitive("dst ", lo, hi))
+    }
+
+    pub fn tcp(self) -> Filter {
+        self.push("tcp")
+    }
+
+    pub fn udp(self) -> Filter {
+        self.push("udp")
+    }
+
+    pub fn ip(self) -> Filter {
+        self.push("ip")
+    }
+
+    pub fn ip6(self) -> Filter {
+        self.push("ip6")
+    }
+
+    /// Another filter as a single parenthesized primitive
+    pub fn group(self, filter: Filter) -> Filter {
+        if filter.expr.is_empty() {
+            self
+        } else {
+            self.push(&format!("({})", filter.expr))
+        }
+    }
+
+    /// An arbitrary pcap-filter expression, parenthesized
+    pub fn raw(self, expr: &str) -> Filter {
+        self.push(&format!("({})", expr))
+    }
+
+    pub fn build(&self) -> String {
+        self.expr.clone()
+    }
+}
+
+/// `portrange lo-hi` with the bounds in order, or `port` for a single port
+fn range_primitive(direction: &str, lo: u16, hi: u16) -> String {
+    let (lo, hi) = (lo.min(hi), lo.max(hi));
+    if lo == hi {
+        format!("{}port {}", direction, lo)
+    } else {
+        format!("{}portrange {}-{}", direction, lo, hi)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expr)
+    }
+}
+
+/// Whether compiling `filter` needs the real netmask rather than
+/// `PCAP_NETMASK_UNKNOWN`, so the netmask only has to be looked up (see
+/// `Handle::netmask_for_filter`) when it matters.
+///
+/// A lexical check: of the primitives libpcap compiles today only
+/// `ip broadcast` uses the netmask. `ip multicast` and plain `broadcast`,
+/// the Ethernet one, don't. Keywords inside quoted strings are not told
+/// apart, which at worst costs an unneeded lookup.
+pub fn filter_needs_netmask(filter: &str) -> bool {
+    let tokens: Vec<&str> = filter
+        .split(|c: char| c.is_whitespace() || c == '(' || c == ')' || c == '!')
+        .filter(|token| !token.is_empty())
+        .collect();
+    tokens.windows(2).any(|pair| pair == ["ip", "broadcast"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn groups_when_the_connective_changes() {
+        let a = IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1));
+        let b = IpAddr::V6(Ipv6Addr::LOCALHOST);
+
+        let filter = Filter::new()
+            .udp()
+            .or()
+            .tcp()
+            .and()
+            .negate()
+            .any_host(vec![a, b])
+            .group(Filter::new().port(53).or().port(853));
+        assert_eq!(
+            filter.build(),
+            "(udp or tcp) and not (host 192.168.0.1 or host ::1) and (port 53 or port 853)"
+        );
+        assert_eq!(Filter::new().net(IpAddr::V6(Ipv6Addr::LOCALHOST), 64).build(), "net ::/64");
+        assert_eq!(Filter::new().or().build(), "");
+    }
+
+    #[test]
+    fn port_ranges() {
+        let filter = Filter::new().udp().dst_port_range(49152, 65535).or().src_port_range(80, 80);
+        assert_eq!(filter.build(), "(udp and dst portrange 49152-65535) or src port 80");
+        assert_eq!(Filter::new().port_range(2000, 1000).build(), "portrange 1000-2000");
+    }
+
+    #[test]
+    fn detects_filters_needing_a_netmask() {
+        assert!(filter_needs_netmask("ip broadcast"));
+        assert!(filter_needs_netmask("tcp or (ip  broadcast)"));
+        assert!(filter_needs_netmask("!(ip broadcast)"));
+        assert!(!filter_needs_netmask("broadcast"));
+        assert!(!filter_needs_netmask("ip multicast"));
+        assert!(!filter_needs_netmask("ip and broadcast"));
+        assert!(!filter_needs_netmask(&Filter::new().tcp().port(443).build()));
+    }
+}
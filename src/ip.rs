@@ -0,0 +1,200 @@
+//! Zero-copy views over captured IPv4 and IPv6 packets
+//!
+//! The views only check that the fixed part of the header is present; any
+//! accessor that reaches past the captured data is clamped to what is there.
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+
+/// An IPv4 or IPv6 packet, starting at the IP header
+#[derive(Clone,Copy,Debug)]
+pub enum IpPacket<'a> {
+    V4(Ipv4Packet<'a>),
+    V6(Ipv6Packet<'a>),
+}
+
+impl<'a> IpPacket<'a> {
+    /// Dispatch on the version nibble
+    pub fn parse(data: &'a [u8]) -> Option<IpPacket<'a>> {
+        match data.first()? >> 4 {
+            4 => Ipv4Packet::new(data).map(IpPacket::V4),
+            6 => Ipv6Packet::new(data).map(IpPacket::V6),
+            _ => None,
+        }
+    }
+
+    /// The transport protocol, after any IPv6 extension headers
+    pub fn protocol(&self) -> u8 {
+        match self {
+            IpPacket::V4(packet) => packet.protocol(),
+            IpPacket::V6(packet) => packet.upper_layer().0,
+        }
+    }
+
+    /// The transport-layer data, after any IPv6 extension headers
+    pub fn payload(&self) -> &'a [u8] {
+        match self {
+            IpPacket::V4(packet) => packet.payload(),
+            IpPacket::V6(packet) => packet.upper_layer().1,
+        }
+    }
+
+    /// Whether less than the whole packet was captured
+    pub fn is_truncated(&self) -> bool {
+        match self {
+            IpPacket::V4(packet) => packet.is_truncated(),
+            IpPacket::V6(packet) => packet.is_truncated(),
+        }
+    }
+}
+
+#[derive(Clone,Copy,Debug)]
+pub struct Ipv4Packet<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Ipv4Packet<'a> {
+    pub fn new(data: &'a [u8]) -> Option<Ipv4Packet<'a>> {
+        if data.len() < 20 || data[0] >> 4 != 4 {
+            return None;
+        }
+        let packet = Ipv4Packet { data };
+        if packet.header_len() < 20 || packet.header_len() > data.len() {
+            return None;
+        }
+        Some(packet)
+    }
+
+    fn u16_at(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.data[offset], self.data[offset + 1]])
+    }
+
+    pub fn header_len(&self) -> usize {
+        usize::from(self.data[0] & 0x0f) * 4
+    }
+
+    pub fn total_len(&self) -> usize {
+        usize::from(self.u16_at(2))
+    }
+
+    pub fn identification(&self) -> u16 {
+        self.u16_at(4)
+    }
+
+    pub fn dont_fragment(&self) -> bool {
+        self.data[6] & 0x40 != 0
+    }
+
+    pub fn more_fragments(&self) -> bool {
+        self.data[6] & 0x20 != 0
+    }
+
+    /// In bytes, not the 8-byte units of the wire format
+    pub fn fragment_offset(&self) -> usize {
+        usize::from(self.u16_at(6) & 0x1fff) * 8
+    }
+
+    pub fn ttl(&self) -> u8 {
+        self.data[8]
+    }
+
+    pub fn protocol(&self) -> u8 {
+        self.data[9]
+    }
+
+    pub fn checksum(&self) -> u16 {
+        self.u16_at(10)
+    }
+
+    pub fn src(&self) -> Ipv4Addr {
+        Ipv4Addr::new(self.data[12], self.data[13], self.data[14], self.data[15])
+    }
+
+    pub fn dst(&self) -> Ipv4Addr {
+        Ipv4Addr::new(self.data[16], self.data[17], self.data[18], self.data[19])
+    }
+
+    /// The header including options
+    pub fn header(&self) -> &'a [u8] {
+        &self.data[..self.header_len()]
+    }
+
+    /// The data after the header, up to `total_len` or the end of the capture
+    pub fn payload(&self) -> &'a [u8] {
+        let end = self.total_len().max(self.header_len()).min(self.data.len());
+        &self.data[self.header_len()..end]
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.data.len() < self.total_len()
+    }
+}
+
+#[derive(Clone,Copy,Debug)]
+pub struct Ipv6Packet<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Ipv6Packet<'a> {
+    pub fn new(data: &'a [u8]) -> Option<Ipv6Packet<'a>> {
+        if data.len() < 40 || data[0] >> 4 != 6 {
+            return None;
+        }
+        Some(Ipv6Packet { data })
+    }
+
+    pub fn payload_len(&self) -> usize {
+        usize::from(u16::from_be_bytes([self.data[4], self.data[5]]))
+    }
+
+    pub fn next_header(&self) -> u8 {
+        self.data[6]
+    }
+
+    pub fn hop_limit(&self) -> u8 {
+        self.data[7]
+    }
+
+    pub fn src(&self) -> Ipv6Addr {
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(&self.data[8..24]);
+        Ipv6Addr::from(octets)
+    }
+
+    pub fn dst(&self) -> Ipv6Addr {
+        let mut octets = [0u8; 16];
+        octets.copy_from_slice(&self.data[24..40]);
+        Ipv6Addr::from(octets)
+    }
+
+    /// Everything after the fixed header, including extension headers
+    pub fn payload(&self) -> &'a [u8] {
+        let end = (40 + self.payload_len()).min(self.data.len());
+        &self.data[40..end]
+    }
+
+    /// The protocol and data following the hop-by-hop, routing, destination
+    /// options and authentication extension headers. Stops at a fragment
+    /// header or anything it doesn't know.
+    pub fn upper_layer(&self) -> (u8, &'a [u8]) {
+        let mut next = self.next_header();
+        let mut rest = self.payload();
+        loop {
+            let len = match next {
+                0 | 43 | 60 if rest.len() >= 2 => (usize::from(rest[1]) + 1) * 8,
+                51 if rest.len() >= 2 => (usize::from(rest[1]) + 2) * 4,
+                _ => return (next, rest),
+            };
+            if len > rest.len() {
+                return (next, rest);
+            }
+            next = rest[0];
+            rest = &rest[len..];
+        }
+    }
+
+    pub fn is_truncated(&self) -> bool {
+        self.data.len() < 40 + self.payload_len()
+    }
+}
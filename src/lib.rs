@@ -1,12 +1,72 @@
-extern crate pcap_sys;
+#[cfg(feature = "libpcap")]
+pub extern crate pcap_sys;
 extern crate bitflags;
 extern crate libc;
 #[cfg(windows)]
 extern crate winapi;
 #[cfg(feature = "libpnet")]
 extern crate pnet;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "etherparse")]
+extern crate etherparse;
+#[cfg(feature = "sha2")]
+extern crate sha2;
 
+// Diagnostics go to the `log` crate when the `log` feature is enabled and
+// are compiled out otherwise. Only the libpcap wrapper uses them so far.
+#[cfg(feature = "log")]
+#[allow(unused_macros)]
+macro_rules! warn {
+    ($($arg:tt)*) => { log::warn!($($arg)*) };
+}
+#[cfg(not(feature = "log"))]
+#[allow(unused_macros)]
+macro_rules! warn {
+    ($($arg:tt)*) => { { let _ = format_args!($($arg)*); } };
+}
+#[cfg(feature = "log")]
+#[allow(unused_macros)]
+macro_rules! debug {
+    ($($arg:tt)*) => { log::debug!($($arg)*) };
+}
+#[cfg(not(feature = "log"))]
+#[allow(unused_macros)]
+macro_rules! debug {
+    ($($arg:tt)*) => { { let _ = format_args!($($arg)*); } };
+}
+
+pub mod checksum;
+pub mod filter;
+pub mod ip;
+pub mod link;
+pub mod monotonic;
+pub mod pcapng;
+pub mod pool;
+pub mod reassembly;
+pub mod savefile;
+pub mod types;
+
+#[cfg(test)]
+mod testutil;
+
+#[cfg(feature = "libpcap")]
 pub mod core;
+#[cfg(feature = "libpcap")]
+pub mod replay;
+#[cfg(feature = "libpcap")]
+pub mod retry;
+#[cfg(feature = "libpcap")]
+pub mod snaplen;
+#[cfg(feature = "libpcap")]
+pub mod split;
+#[cfg(feature = "libpcap")]
+pub mod stats;
+#[cfg(feature = "libpcap")]
+pub mod watch;
 
 #[cfg(feature = "libpnet")]
 pub mod libpnet;
+
+#[cfg(feature = "etherparse")]
+pub mod libetherparse;
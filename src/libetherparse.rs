@@ -0,0 +1,119 @@
+//! Handing captured packets to the `etherparse` crate
+//!
+//! etherparse has a separate entry point for each kind of link-layer header,
+//! so the caller would otherwise have to match on the handle's link type
+//! themselves. `slice_packet` does that match.
+use etherparse::{err, EtherType, SlicedPacket};
+use link::{loopback_protocol, LoopbackProtocol};
+use types::{DataLinkType, Packet};
+
+/// The reasons `slice_packet` can fail
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SliceError {
+    /// etherparse has no entry point for this link type
+    UnsupportedLink(DataLinkType),
+    /// a loopback frame carrying neither IPv4 nor IPv6
+    UnsupportedProtocol(u32),
+    /// too short for the link-layer header
+    Truncated,
+    /// etherparse rejected the packet
+    Etherparse(err::packet::SliceError),
+}
+
+impl From<err::packet::SliceError> for SliceError {
+    fn from(err: err::packet::SliceError) -> SliceError {
+        SliceError::Etherparse(err)
+    }
+}
+
+/// Slice `data` (a packet from a handle with link type `dlt`) with whichever
+/// etherparse entry point fits: Ethernet, Linux cooked v1 and v2, raw IP, and
+/// the BSD loopback encapsulations are understood.
+pub fn slice_packet(dlt: DataLinkType, data: &[u8]) -> Result<SlicedPacket<'_>, SliceError> {
+    let sliced = match dlt {
+        DataLinkType::En10Mb => SlicedPacket::from_ethernet(data)?,
+        DataLinkType::LinuxSll => SlicedPacket::from_linux_sll(data)?,
+        DataLinkType::LinuxSll2 => {
+            // etherparse doesn't know SLL2; the protocol leads its 20 bytes
+            if data.len() < 20 {
+                return Err(SliceError::Truncated);
+            }
+            let ether_type = EtherType(u16::from_be_bytes([data[0], data[1]]));
+            SlicedPacket::from_ether_type(ether_type, &data[20..])?
+        }
+        DataLinkType::Raw | DataLinkType::Ipv4 | DataLinkType::Ipv6 => SlicedPacket::from_ip(data)?,
+        DataLinkType::Null | DataLinkType::Loop => match loopback_protocol(dlt, data) {
+            Some(LoopbackProtocol::Ipv4) | Some(LoopbackProtocol::Ipv6) => SlicedPacket::from_ip(&data[4..])?,
+            Some(LoopbackProtocol::Other(family)) => return Err(SliceError::UnsupportedProtocol(family)),
+            None => return Err(SliceError::Truncated),
+        },
+        _ => return Err(SliceError::UnsupportedLink(dlt)),
+    };
+    Ok(sliced)
+}
+
+impl Packet {
+    /// `slice_packet` on this packet's data
+    pub fn sliced(&self, dlt: DataLinkType) -> Result<SlicedPacket<'_>, SliceError> {
+        slice_packet(dlt, &self.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use etherparse::{InternetSlice, LinkSlice, TransportSlice};
+
+    // IPv4/UDP 10.0.0.1:1000 -> 10.0.0.2:2000, no payload, checksums zeroed
+    const IPV4_UDP: [u8; 28] = [
+        0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+        0x03, 0xe8, 0x07, 0xd0, 0, 8, 0, 0,
+    ];
+
+    fn udp_ports(sliced: &SlicedPacket) -> (u16, u16) {
+        match sliced.transport {
+            Some(TransportSlice::Udp(ref udp)) => (udp.source_port(), udp.destination_port()),
+            ref other => panic!("expected UDP, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn slices_ethernet() {
+        let mut frame = vec![0xff; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&IPV4_UDP);
+        let sliced = slice_packet(DataLinkType::En10Mb, &frame).unwrap();
+        assert!(matches!(sliced.link, Some(LinkSlice::Ethernet2(_))));
+        assert_eq!(udp_ports(&sliced), (1000, 2000));
+    }
+
+    #[test]
+    fn slices_linux_sll2_and_loopback() {
+        let mut sll2 = vec![0x08, 0x00];
+        sll2.extend_from_slice(&[0; 18]);
+        sll2.extend_from_slice(&IPV4_UDP);
+        let sliced = slice_packet(DataLinkType::LinuxSll2, &sll2).unwrap();
+        assert!(matches!(sliced.net, Some(InternetSlice::Ipv4(_))));
+        assert_eq!(udp_ports(&sliced), (1000, 2000));
+
+        let mut lo = vec![0, 0, 0, 2];
+        lo.extend_from_slice(&IPV4_UDP);
+        assert_eq!(udp_ports(&slice_packet(DataLinkType::Loop, &lo).unwrap()), (1000, 2000));
+        lo[3] = 7;
+        assert_eq!(slice_packet(DataLinkType::Loop, &lo).unwrap_err(), SliceError::UnsupportedProtocol(7));
+    }
+
+    #[test]
+    fn slices_raw_ip() {
+        let sliced = slice_packet(DataLinkType::Raw, &IPV4_UDP).unwrap();
+        assert!(sliced.link.is_none());
+        assert_eq!(udp_ports(&sliced), (1000, 2000));
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_slice() {
+        assert_eq!(slice_packet(DataLinkType::LinuxSll2, &[0; 10]).unwrap_err(), SliceError::Truncated);
+        assert_eq!(slice_packet(DataLinkType::Null, &[2, 0]).unwrap_err(), SliceError::Truncated);
+        assert!(matches!(slice_packet(DataLinkType::En10Mb, &[0; 4]), Err(SliceError::Etherparse(_))));
+    }
+}
@@ -1,64 +1,79 @@
-use pnet::packet::ethernet::{EthernetPacket, EtherTypes};
+//! Views of captured packets as `pnet` packets
+//!
+//! The link-layer header is stripped according to the handle's link type
+//! (see `link::network_layer`), so the IP views work on Linux cooked and
+//! loopback captures as well as on Ethernet.
+use link::network_layer;
+use pnet::packet::ethernet::EthernetPacket;
 use pnet::packet::ipv4::Ipv4Packet;
 use pnet::packet::ipv6::Ipv6Packet;
-use pnet::packet::Packet;
-use pnet::packet::tcp::TcpPacket;
-use core::PacketHeader;
-use core;
+use types::DataLinkType;
 
-fn convert_packet<'a>(datalink_type: i32, _header: PacketHeader, packet_data: Vec<u8>) -> EthernetPacket<'a> {
-    if datalink_type != 1 {
-        panic!("Not reading Ethernet packets");
+/// `data` as an Ethernet frame. `None` unless `dlt` is `En10Mb` and the
+/// frame holds a whole Ethernet header.
+pub fn ethernet(dlt: DataLinkType, data: &[u8]) -> Option<EthernetPacket<'_>> {
+    match dlt {
+        DataLinkType::En10Mb => EthernetPacket::new(data),
+        _ => None,
     }
+}
 
-    EthernetPacket::owned(packet_data).unwrap()
+fn ip_version(data: &[u8]) -> Option<u8> {
+    data.first().map(|b| b >> 4)
 }
 
-pub fn test() {
-    match core::find_all_devs() {
-        Ok(pcap_ifs) => pcap_ifs.for_each(|interface| println!("{:?}", interface)),
-        Err(e) => println!("{:?}", e)
+/// The IPv4 packet inside `data`, if that's what it carries
+pub fn ipv4(dlt: DataLinkType, data: &[u8]) -> Option<Ipv4Packet<'_>> {
+    let payload = network_layer(dlt, data)?;
+    if ip_version(payload)? != 4 {
+        return None;
     }
+    Ipv4Packet::new(payload)
+}
 
-    // 0 will use pcap default
-    // For old versions the default was 68 bytes
-    // Later it was increased to 65535 bytes
-    // And in the most recent versions it is 262144 bytes
-    let default_snaplen = 0;
-
-    // 0 will loop forever
-    // Any other positive number will exit after that many packets have been received
-    let num_packets = 0;
-    match core::open_live("en0", default_snaplen, true, 10) {
-        Ok(handle) => {
-            let mut filter = handle.compile("tcp port 443", true, 0);
-            handle.setfilter(&mut filter);
-            handle.loop_(0, |header, packet| {
-                let packet = convert_packet(1, header, packet);
-                match packet.get_ethertype() {
-                    EtherTypes::Ipv4 => {
-                        let ipv4_packet = Ipv4Packet::owned(packet.payload().to_vec()).unwrap();
-                        let tcp_packet = TcpPacket::owned(ipv4_packet.payload().to_vec()).unwrap();
-                        println!("{:?}", tcp_packet);
-                    }
-                    EtherTypes::Ipv6 => {
-                        let ipv6_packet = Ipv6Packet::owned(packet.payload().to_vec()).unwrap();
-                        let tcp_packet = TcpPacket::owned(ipv6_packet.payload().to_vec()).unwrap();
-                        println!("{:?}", tcp_packet);
-                    }
-                    other => println!("Unhandled ethertype: {:?}", other)
-                }
-            })
-        }
-        Err(e) => println!("{:?}", e)
+/// The IPv6 packet inside `data`, if that's what it carries
+pub fn ipv6(dlt: DataLinkType, data: &[u8]) -> Option<Ipv6Packet<'_>> {
+    let payload = network_layer(dlt, data)?;
+    if ip_version(payload)? != 6 {
+        return None;
     }
+    Ipv6Packet::new(payload)
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    use pnet::packet::ethernet::EtherTypes;
+    use pnet::packet::Packet;
+    use std::net::Ipv4Addr;
+
+    const IPV4_UDP: [u8; 28] = [
+        0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+        0x03, 0xe8, 0x07, 0xd0, 0, 8, 0, 0,
+    ];
+
     #[test]
-    fn it_works() {
-        super::test();
-        assert_eq!(2 + 2, 4);
+    fn views_ethernet_and_ipv4() {
+        let mut frame = vec![0xff; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(&IPV4_UDP);
+
+        let eth = ethernet(DataLinkType::En10Mb, &frame).unwrap();
+        assert_eq!(eth.get_ethertype(), EtherTypes::Ipv4);
+        assert_eq!(eth.payload(), &IPV4_UDP[..]);
+
+        let ip = ipv4(DataLinkType::En10Mb, &frame).unwrap();
+        assert_eq!(ip.get_destination(), Ipv4Addr::new(10, 0, 0, 2));
+        assert!(ipv6(DataLinkType::En10Mb, &frame).is_none());
+    }
+
+    #[test]
+    fn accounts_for_the_link_type() {
+        assert!(ethernet(DataLinkType::Raw, &IPV4_UDP).is_none());
+        assert_eq!(ipv4(DataLinkType::Raw, &IPV4_UDP).unwrap().get_source(), Ipv4Addr::new(10, 0, 0, 1));
+
+        let mut lo = vec![0, 0, 0, 2];
+        lo.extend_from_slice(&IPV4_UDP);
+        assert_eq!(ipv4(DataLinkType::Loop, &lo).unwrap().get_ttl(), 64);
     }
 }
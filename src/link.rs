@@ -0,0 +1,481 @@
+//! Stripping link-layer headers to get at the IP packet inside
+//!
+//! Which header precedes the IP packet depends on the handle's link type;
+//! getting the offset wrong is the classic way to end up parsing garbage.
+//! `network_layer` knows the common ones so callers don't have to.
+#[cfg(feature = "libpcap")]
+use core::{CaptureConfig, Error};
+use ip::IpPacket;
+use types::{DataLinkType, PacketHeader, MAX_SNAPLEN};
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86dd;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88a8;
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn by_ethertype(ethertype: u16, payload: &[u8]) -> Option<&[u8]> {
+    match ethertype {
+        ETHERTYPE_IPV4 | ETHERTYPE_IPV6 => Some(payload),
+        _ => None,
+    }
+}
+
+/// The length in bytes of the frame check sequence at the end of every
+/// packet, as recorded in the upper bits of a savefile's link type (what
+/// `pcap_datalink_ext` returns). `None` if the file doesn't say, which
+/// includes every live capture: most drivers strip the FCS, but some can be
+/// told to keep it and libpcap can't tell which.
+pub fn fcs_len(linktype_ext: i32) -> Option<usize> {
+    const FCS_LENGTH_PRESENT: u32 = 0x0400_0000;
+    let raw = linktype_ext as u32;
+    if raw & FCS_LENGTH_PRESENT == 0 {
+        return None;
+    }
+    // counted in 16-bit words
+    Some((raw >> 28) as usize * 2)
+}
+
+/// `data` without the last `fcs_len` bytes of the original frame. The cut is
+/// made relative to `header.len`, so a packet truncated by the snaplen only
+/// loses whatever part of the FCS it captured.
+pub fn strip_fcs<'a>(header: &PacketHeader, data: &'a [u8], fcs_len: usize) -> &'a [u8] {
+    let end = (header.len as usize).saturating_sub(fcs_len).min(data.len());
+    &data[..end]
+}
+
+/// A snaplen that captures whole Ethernet frames on a link with this MTU:
+/// the MTU plus the Ethernet header and room for two VLAN tags. Capped at
+/// `MAX_SNAPLEN`, which still holds 9000-byte jumbo frames many times over.
+pub fn snaplen_for_mtu(mtu: u32) -> u32 {
+    const ETHERNET_HEADER_LEN: u32 = 14;
+    const VLAN_HEADROOM: u32 = 2 * 4;
+    mtu.saturating_add(ETHERNET_HEADER_LEN + VLAN_HEADROOM).min(MAX_SNAPLEN)
+}
+
+/// The 802.1Q and 802.1ad (QinQ) tags in front of a frame's ethertype
+///
+/// Some capture paths strip the tag off before the packet is seen and report
+/// it out of band instead, e.g. Linux drivers with VLAN offload. libpcap on
+/// Linux puts such tags back into the data for Ethernet and cooked captures,
+/// but other platforms, or other tools that wrote a savefile, may not; an
+/// empty `ids` doesn't prove the frame was untagged on the wire.
+#[derive(Clone,Debug,Default,Eq,PartialEq)]
+pub struct VlanTags {
+    /// VLAN IDs, outermost first
+    pub ids: Vec<u16>,
+    /// offset of the ethertype after the last tag, the payload starting two
+    /// bytes later
+    pub ethertype_offset: usize,
+}
+
+impl VlanTags {
+    pub fn is_tagged(&self) -> bool {
+        !self.ids.is_empty()
+    }
+}
+
+/// Walk the VLAN tags of an Ethernet or Linux cooked (v1) frame. Returns
+/// `None` for other link types, or if the frame ends inside the headers.
+pub fn vlan_tags(dlt: DataLinkType, data: &[u8]) -> Option<VlanTags> {
+    let mut offset = match dlt {
+        DataLinkType::En10Mb => 12,
+        DataLinkType::LinuxSll => 14,
+        _ => return None,
+    };
+    let mut ids = vec![];
+    let mut ethertype = u16_at(data, offset)?;
+    while ethertype == ETHERTYPE_VLAN || ethertype == ETHERTYPE_QINQ {
+        ids.push(u16_at(data, offset + 2)? & 0x0fff);
+        offset += 4;
+        ethertype = u16_at(data, offset)?;
+    }
+    Some(VlanTags {
+        ids,
+        ethertype_offset: offset,
+    })
+}
+
+/// A zero-copy view of an Ethernet frame. Only the length is checked up
+/// front; each field is read when asked for, so wrapping packets that end up
+/// only being archived costs nothing.
+#[derive(Clone,Copy,Debug)]
+pub struct EthFrame<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> EthFrame<'a> {
+    /// `None` if `data` is shorter than an Ethernet header
+    pub fn new(data: &'a [u8]) -> Option<EthFrame<'a>> {
+        if data.len() < 14 {
+            return None;
+        }
+        Some(EthFrame { data })
+    }
+
+    /// A view of a captured packet, if the link type is Ethernet
+    pub fn from_packet(dlt: DataLinkType, data: &'a [u8]) -> Option<EthFrame<'a>> {
+        match dlt {
+            DataLinkType::En10Mb => EthFrame::new(data),
+            _ => None,
+        }
+    }
+
+    fn mac_at(&self, offset: usize) -> [u8; 6] {
+        let mut mac = [0u8; 6];
+        mac.copy_from_slice(&self.data[offset..offset + 6]);
+        mac
+    }
+
+    pub fn dst_mac(&self) -> [u8; 6] {
+        self.mac_at(0)
+    }
+
+    pub fn src_mac(&self) -> [u8; 6] {
+        self.mac_at(6)
+    }
+
+    /// The type field of the header itself, which is the tag type for a
+    /// VLAN-tagged frame; see `inner_ethertype`
+    pub fn ethertype(&self) -> u16 {
+        u16::from_be_bytes([self.data[12], self.data[13]])
+    }
+
+    /// The data after the 14-byte header
+    pub fn payload(&self) -> &'a [u8] {
+        &self.data[14..]
+    }
+
+    /// See `vlan_tags`
+    pub fn vlan_tags(&self) -> Option<VlanTags> {
+        vlan_tags(DataLinkType::En10Mb, self.data)
+    }
+
+    /// The ethertype after any VLAN tags, `None` if the capture ends inside
+    /// them
+    pub fn inner_ethertype(&self) -> Option<u16> {
+        u16_at(self.data, self.vlan_tags()?.ethertype_offset)
+    }
+
+    /// The data after the header and any VLAN tags
+    pub fn inner_payload(&self) -> Option<&'a [u8]> {
+        self.data.get(self.vlan_tags()?.ethertype_offset + 2..)
+    }
+
+    /// The whole frame as captured
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// What a loopback (`DLT_NULL` or `DLT_LOOP`) frame carries, going by its
+/// 4-byte address family header
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum LoopbackProtocol {
+    Ipv4,
+    Ipv6,
+    /// an address family this crate doesn't know
+    Other(u32),
+}
+
+const AF_INET: u32 = 2;
+/// `AF_INET6` on Linux, Windows, NetBSD and OpenBSD, FreeBSD, and macOS
+const AF_INET6: [u32; 5] = [10, 23, 24, 28, 30];
+
+/// The protocol of a loopback frame. `DLT_LOOP` stores the address family
+/// in network byte order; `DLT_NULL` uses the byte order of the machine that
+/// captured it, which needn't be this one, so both orders are tried.
+/// The payload starts 4 bytes in.
+pub fn loopback_protocol(dlt: DataLinkType, data: &[u8]) -> Option<LoopbackProtocol> {
+    let bytes = data.get(..4)?;
+    let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+    let family = match dlt {
+        DataLinkType::Loop => u32::from_be_bytes(bytes),
+        DataLinkType::Null => {
+            // address families are small, so the right order leaves the
+            // high half empty
+            let family = u32::from_ne_bytes(bytes);
+            if family > 0xffff { family.swap_bytes() } else { family }
+        }
+        _ => return None,
+    };
+    Some(match family {
+        AF_INET => LoopbackProtocol::Ipv4,
+        family if AF_INET6.contains(&family) => LoopbackProtocol::Ipv6,
+        family => LoopbackProtocol::Other(family),
+    })
+}
+
+/// Who a packet in a Linux cooked capture was addressed to, the kernel's
+/// `PACKET_*` value
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum CookedPacketType {
+    /// addressed to this host
+    Host,
+    Broadcast,
+    Multicast,
+    /// addressed to another host, seen in promiscuous mode
+    OtherHost,
+    /// sent by this host
+    Outgoing,
+    Other(u16),
+}
+
+impl CookedPacketType {
+    fn from_raw(raw: u16) -> CookedPacketType {
+        match raw {
+            0 => CookedPacketType::Host,
+            1 => CookedPacketType::Broadcast,
+            2 => CookedPacketType::Multicast,
+            3 => CookedPacketType::OtherHost,
+            4 => CookedPacketType::Outgoing,
+            other => CookedPacketType::Other(other),
+        }
+    }
+}
+
+/// The header of a Linux cooked capture (`DataLinkType::LinuxSll` or
+/// `LinuxSll2`), which stands in for the link layer on the `any` device
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct CookedHeader {
+    pub packet_type: CookedPacketType,
+    /// `ARPHRD_*` type of the interface, e.g. 1 for Ethernet
+    pub arphrd_type: u16,
+    /// the ethertype of the payload
+    pub protocol: u16,
+    /// index of the interface the packet was seen on; only in SLL2 headers
+    pub if_index: Option<u32>,
+    addr: [u8; 8],
+    addr_len: usize,
+}
+
+impl CookedHeader {
+    /// The link-layer source address, as much of it as fits in the header
+    pub fn link_addr(&self) -> &[u8] {
+        &self.addr[..self.addr_len]
+    }
+
+    /// 16 bytes for SLL, 20 for SLL2; the payload starts here
+    pub fn header_len(&self) -> usize {
+        if self.if_index.is_some() { 20 } else { 16 }
+    }
+}
+
+/// Parse the cooked-capture header at the start of `data`. `None` for other
+/// link types and packets too short for the header.
+pub fn cooked_header(dlt: DataLinkType, data: &[u8]) -> Option<CookedHeader> {
+    let (packet_type, arphrd_type, addr_len, addr, protocol, if_index) = match dlt {
+        DataLinkType::LinuxSll => (
+            u16_at(data, 0)?,
+            u16_at(data, 2)?,
+            u16_at(data, 4)? as usize,
+            data.get(6..14)?,
+            u16_at(data, 14)?,
+            None,
+        ),
+        DataLinkType::LinuxSll2 => {
+            let if_index = data.get(4..8)?;
+            (
+                u16::from(*data.get(10)?),
+                u16_at(data, 8)?,
+                usize::from(*data.get(11)?),
+                data.get(12..20)?,
+                u16_at(data, 0)?,
+                Some(u32::from_be_bytes([if_index[0], if_index[1], if_index[2], if_index[3]])),
+            )
+        }
+        _ => return None,
+    };
+    let mut header = CookedHeader {
+        packet_type: CookedPacketType::from_raw(packet_type),
+        arphrd_type,
+        protocol,
+        if_index,
+        addr: [0; 8],
+        addr_len: addr_len.min(8),
+    };
+    header.addr.copy_from_slice(addr);
+    Some(header)
+}
+
+/// Whether `network_layer` understands this link type
+pub fn is_supported(dlt: DataLinkType) -> bool {
+    matches!(
+        dlt,
+        DataLinkType::En10Mb
+            | DataLinkType::LinuxSll
+            | DataLinkType::LinuxSll2
+            | DataLinkType::Raw
+            | DataLinkType::Ipv4
+            | DataLinkType::Ipv6
+            | DataLinkType::Null
+            | DataLinkType::Loop
+    )
+}
+
+/// The data after the link-layer header, if it carries IPv4 or IPv6.
+/// 802.1Q and 802.1ad VLAN tags are skipped, see `vlan_tags`.
+pub fn network_layer(dlt: DataLinkType, data: &[u8]) -> Option<&[u8]> {
+    match dlt {
+        DataLinkType::En10Mb | DataLinkType::LinuxSll => {
+            let offset = vlan_tags(dlt, data)?.ethertype_offset;
+            by_ethertype(u16_at(data, offset)?, data.get(offset + 2..)?)
+        }
+        DataLinkType::LinuxSll2 => by_ethertype(u16_at(data, 0)?, data.get(20..)?),
+        DataLinkType::Raw | DataLinkType::Ipv4 | DataLinkType::Ipv6 => Some(data),
+        DataLinkType::Null | DataLinkType::Loop => match loopback_protocol(dlt, data)? {
+            LoopbackProtocol::Ipv4 | LoopbackProtocol::Ipv6 => data.get(4..),
+            LoopbackProtocol::Other(_) => None,
+        },
+        _ => None,
+    }
+}
+
+/// `network_layer`, parsed as an IP packet
+pub fn ip_packet<'a>(dlt: DataLinkType, data: &'a [u8]) -> Option<IpPacket<'a>> {
+    IpPacket::parse(network_layer(dlt, data)?)
+}
+
+/// Open `device` with default settings and call `f` with the IP packet of
+/// each of `count` captured packets (forever if `count` is 0 or negative),
+/// whatever the device's link type. Packets that aren't IP, or are too short
+/// to parse, are skipped but still count.
+///
+/// Fails up front if the device's link type isn't one `network_layer`
+/// understands.
+#[cfg(feature = "libpcap")]
+pub fn capture_parsed<F: FnMut(&PacketHeader, IpPacket)>(
+    device: &str,
+    count: i32,
+    mut f: F,
+) -> Result<(), Error> {
+    let handle = CaptureConfig::new(device).open()?;
+    let dlt = handle.datalink_type();
+    if !is_supported(dlt) {
+        return Err(Error::with_message(-1, &format!("unsupported link type {:?}", dlt)));
+    }
+    handle.loop_(count, |header, data| {
+        if let Some(packet) = ip_packet(dlt, data) {
+            f(&header, packet)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::TimeStamp;
+
+    #[test]
+    fn strips_common_link_layers() {
+        let ip = [0x45, 0, 0, 20, 0, 0, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2];
+
+        let mut tagged = vec![0u8; 12];
+        tagged.extend_from_slice(&[0x81, 0x00, 0x00, 0x05, 0x08, 0x00]);
+        tagged.extend_from_slice(&ip);
+        assert_eq!(network_layer(DataLinkType::En10Mb, &tagged), Some(&ip[..]));
+
+        let mut sll = vec![0u8; 14];
+        sll.extend_from_slice(&[0x08, 0x00]);
+        sll.extend_from_slice(&ip);
+        assert_eq!(network_layer(DataLinkType::LinuxSll, &sll), Some(&ip[..]));
+
+        let mut null = vec![2, 0, 0, 0];
+        null.extend_from_slice(&ip);
+        assert!(ip_packet(DataLinkType::Null, &null).is_some());
+
+        let mut arp = vec![0u8; 12];
+        arp.extend_from_slice(&[0x08, 0x06]);
+        assert_eq!(network_layer(DataLinkType::En10Mb, &arp), None);
+        assert_eq!(network_layer(DataLinkType::Ieee802_11, &ip), None);
+    }
+
+    #[test]
+    fn eth_frame_reads_fields_on_demand() {
+        let mut tagged = vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        tagged.extend_from_slice(&[0x81, 0x00, 0x00, 0x05, 0x86, 0xdd, 0xaa]);
+        let frame = EthFrame::from_packet(DataLinkType::En10Mb, &tagged).unwrap();
+        assert_eq!(frame.dst_mac(), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(frame.src_mac(), [7, 8, 9, 10, 11, 12]);
+        assert_eq!(frame.ethertype(), ETHERTYPE_VLAN);
+        assert_eq!(frame.payload(), [0x00, 0x05, 0x86, 0xdd, 0xaa]);
+        assert_eq!(frame.inner_ethertype(), Some(ETHERTYPE_IPV6));
+        assert_eq!(frame.inner_payload(), Some(&[0xaa][..]));
+
+        assert!(EthFrame::new(&tagged[..13]).is_none());
+        assert!(EthFrame::from_packet(DataLinkType::Raw, &tagged).is_none());
+    }
+
+    #[test]
+    fn reads_loopback_address_families() {
+        let swapped_null = 30u32.swap_bytes().to_ne_bytes();
+        assert_eq!(loopback_protocol(DataLinkType::Null, &2u32.to_ne_bytes()), Some(LoopbackProtocol::Ipv4));
+        assert_eq!(loopback_protocol(DataLinkType::Null, &swapped_null), Some(LoopbackProtocol::Ipv6));
+        assert_eq!(loopback_protocol(DataLinkType::Loop, &[0, 0, 0, 24]), Some(LoopbackProtocol::Ipv6));
+        assert_eq!(loopback_protocol(DataLinkType::Loop, &[0, 0, 0, 7]), Some(LoopbackProtocol::Other(7)));
+        assert_eq!(loopback_protocol(DataLinkType::Loop, &[0, 0, 2]), None);
+        assert_eq!(loopback_protocol(DataLinkType::En10Mb, &[0, 0, 0, 2]), None);
+    }
+
+    #[test]
+    fn sizes_snaplen_from_mtu() {
+        assert_eq!(snaplen_for_mtu(1500), 1522);
+        assert_eq!(snaplen_for_mtu(9000), 9022);
+        assert_eq!(snaplen_for_mtu(u32::MAX), MAX_SNAPLEN);
+    }
+
+    #[test]
+    fn parses_cooked_headers() {
+        let mut sll = vec![0, 4, 0, 1, 0, 6, 2, 0, 0, 0, 0, 1, 0, 0, 0x86, 0xdd];
+        sll.extend_from_slice(&[0x60, 0, 0, 0]);
+        let header = cooked_header(DataLinkType::LinuxSll, &sll).unwrap();
+        assert_eq!(header.packet_type, CookedPacketType::Outgoing);
+        assert_eq!((header.arphrd_type, header.protocol, header.if_index), (1, 0x86dd, None));
+        assert_eq!(header.link_addr(), [2, 0, 0, 0, 0, 1]);
+        assert_eq!(header.header_len(), 16);
+
+        let sll2 = [0x08, 0x00, 0, 0, 0, 0, 0, 3, 0, 1, 0, 6, 2, 0, 0, 0, 0, 2, 0, 0];
+        let header = cooked_header(DataLinkType::LinuxSll2, &sll2).unwrap();
+        assert_eq!(header.packet_type, CookedPacketType::Host);
+        assert_eq!((header.protocol, header.if_index), (0x0800, Some(3)));
+        assert_eq!(header.link_addr(), [2, 0, 0, 0, 0, 2]);
+        assert_eq!(header.header_len(), 20);
+
+        assert_eq!(cooked_header(DataLinkType::LinuxSll2, &sll2[..19]), None);
+        assert_eq!(cooked_header(DataLinkType::En10Mb, &sll2), None);
+    }
+
+    #[test]
+    fn reads_and_strips_the_fcs() {
+        assert_eq!(fcs_len(1), None);
+        assert_eq!(fcs_len(0x2400_0001), Some(4));
+        assert_eq!(fcs_len(0x0400_0001), Some(0));
+
+        let frame = [1u8, 2, 3, 4, 5, 6, 0xaa, 0xbb, 0xcc, 0xdd];
+        let header = |caplen| PacketHeader { ts: TimeStamp { sec: 0, usec: 0 }, caplen, len: 10 };
+        assert_eq!(strip_fcs(&header(10), &frame, 4), &frame[..6]);
+        assert_eq!(strip_fcs(&header(8), &frame[..8], 4), &frame[..6]);
+        assert_eq!(strip_fcs(&header(4), &frame[..4], 4), &frame[..4]);
+    }
+
+    #[test]
+    fn finds_stacked_vlan_tags() {
+        let mut qinq = vec![0u8; 12];
+        qinq.extend_from_slice(&[0x88, 0xa8, 0x20, 0x64, 0x81, 0x00, 0x00, 0x05, 0x08, 0x00]);
+        let tags = vlan_tags(DataLinkType::En10Mb, &qinq).unwrap();
+        assert_eq!(tags.ids, [100, 5]);
+        assert_eq!(tags.ethertype_offset, 20);
+
+        let mut plain = vec![0u8; 12];
+        plain.extend_from_slice(&[0x08, 0x00]);
+        let untagged = vlan_tags(DataLinkType::En10Mb, &plain).unwrap();
+        assert!(!untagged.is_tagged());
+        assert_eq!(untagged.ethertype_offset, 12);
+        assert_eq!(vlan_tags(DataLinkType::En10Mb, &qinq[..17]), None);
+        assert_eq!(vlan_tags(DataLinkType::Raw, &qinq), None);
+    }
+}
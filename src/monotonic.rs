@@ -0,0 +1,134 @@
+//! Catching packet timestamps that go backwards
+//!
+//! Captures are expected to be in time order, but hardware timestamping
+//! glitches, merged files and corruption all break that. `MonotonicChecker`
+//! counts (and optionally repairs) the packets that are out of order.
+use types::{PacketHeader, TimeStamp};
+
+/// Remembers the latest timestamp seen and flags packets that are earlier
+#[derive(Clone,Debug,Default)]
+pub struct MonotonicChecker {
+    last: Option<TimeStamp>,
+    clamp: bool,
+    anomalies: u64,
+}
+
+impl MonotonicChecker {
+    /// Count out-of-order packets but leave their timestamps alone
+    pub fn new() -> MonotonicChecker {
+        MonotonicChecker::default()
+    }
+
+    /// Count out-of-order packets and rewrite their timestamps to the
+    /// latest one seen, so the stream is non-decreasing
+    pub fn clamping() -> MonotonicChecker {
+        MonotonicChecker {
+            clamp: true,
+            ..MonotonicChecker::default()
+        }
+    }
+
+    /// Returns `false` if `header` is earlier than a previous packet
+    pub fn check(&mut self, header: &mut PacketHeader) -> bool {
+        match &self.last {
+            Some(last) if header.ts < *last => {
+                self.anomalies += 1;
+                if self.clamp {
+                    header.ts = last.clone();
+                }
+                false
+            }
+            _ => {
+                self.last = Some(header.ts.clone());
+                true
+            }
+        }
+    }
+
+    /// Packets found out of order so far
+    pub fn anomalies(&self) -> u64 {
+        self.anomalies
+    }
+}
+
+/// Anything carrying a packet header a `Monotonic` iterator can check
+pub trait HasHeader {
+    /// `None` for items without a packet, such as errors
+    fn header_mut(&mut self) -> Option<&mut PacketHeader>;
+}
+
+impl<T> HasHeader for (PacketHeader, T) {
+    fn header_mut(&mut self) -> Option<&mut PacketHeader> {
+        Some(&mut self.0)
+    }
+}
+
+impl<T: HasHeader, E> HasHeader for Result<T, E> {
+    fn header_mut(&mut self) -> Option<&mut PacketHeader> {
+        self.as_mut().ok().and_then(HasHeader::header_mut)
+    }
+}
+
+/// Iterator adapter returned by `CheckMonotonic::check_monotonic`
+#[derive(Debug)]
+pub struct Monotonic<I> {
+    inner: I,
+    checker: MonotonicChecker,
+}
+
+impl<I> Monotonic<I> {
+    /// Packets found out of order so far
+    pub fn anomalies(&self) -> u64 {
+        self.checker.anomalies()
+    }
+
+    pub fn into_inner(self) -> I {
+        self.inner
+    }
+}
+
+impl<I: Iterator> Iterator for Monotonic<I>
+where
+    I::Item: HasHeader,
+{
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let mut item = self.inner.next()?;
+        if let Some(header) = item.header_mut() {
+            self.checker.check(header);
+        }
+        Some(item)
+    }
+}
+
+/// Adds `check_monotonic` to packet iterators such as `PcapReader`
+pub trait CheckMonotonic: Iterator + Sized {
+    /// Count packets whose timestamp goes backwards, and with `clamp` set
+    /// them to the latest timestamp seen
+    fn check_monotonic(self, clamp: bool) -> Monotonic<Self> {
+        Monotonic {
+            inner: self,
+            checker: if clamp { MonotonicChecker::clamping() } else { MonotonicChecker::new() },
+        }
+    }
+}
+
+impl<I: Iterator> CheckMonotonic for I where I::Item: HasHeader {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(sec: i64) -> (PacketHeader, ()) {
+        (PacketHeader { ts: TimeStamp { sec, usec: 0 }, caplen: 0, len: 0 }, ())
+    }
+
+    #[test]
+    fn clamps_backwards_timestamps() {
+        let mut packets = vec![packet(1), packet(3), packet(2), packet(4)].into_iter().check_monotonic(true);
+        let seconds: Vec<i64> = packets.by_ref().map(|(header, _)| header.ts.sec).collect();
+        assert_eq!(seconds, [1, 3, 3, 4]);
+        assert_eq!(packets.anomalies(), 1);
+    }
+}
@@ -0,0 +1,620 @@
+//! Pure-Rust reader and writer for the pcapng capture format
+//!
+//! Unlike the classic savefile format, pcapng can carry free-text comments
+//! for the whole file and for individual packets, and packets from several
+//! interfaces with different link types. Files are written in host byte
+//! order with a single section and a single interface; any file can be read.
+use savefile::{read_full, truncated, u32_at};
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use types::{Error, PacketHeader, TimeStamp, TstampPrecision};
+
+const BLOCK_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+const BLOCK_INTERFACE_DESCRIPTION: u32 = 1;
+const BLOCK_SIMPLE_PACKET: u32 = 3;
+const BLOCK_ENHANCED_PACKET: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+/// Blocks longer than this are taken to be corruption rather than allocated
+const MAX_BLOCK_LEN: usize = 16 * 1024 * 1024;
+
+const OPT_ENDOFOPT: u16 = 0;
+const OPT_COMMENT: u16 = 1;
+const IF_TSRESOL: u16 = 9;
+const IF_TSOFFSET: u16 = 14;
+
+/// Whether `start`, the first bytes of a file, looks like pcapng. The
+/// section header block type reads the same in either byte order.
+pub fn is_pcapng(start: &[u8]) -> bool {
+    start.starts_with(&BLOCK_SECTION_HEADER.to_ne_bytes())
+}
+
+/// Pad `buf` with zeros to a multiple of four bytes
+fn pad(buf: &mut Vec<u8>) {
+    let padded = (buf.len() + 3) & !3;
+    buf.resize(padded, 0);
+}
+
+fn push_option(buf: &mut Vec<u8>, code: u16, value: &[u8]) {
+    buf.extend_from_slice(&code.to_ne_bytes());
+    buf.extend_from_slice(&(value.len() as u16).to_ne_bytes());
+    buf.extend_from_slice(value);
+    pad(buf);
+}
+
+/// Terminate an option list; an empty list is left out entirely
+fn end_options(buf: &mut Vec<u8>, options: &[u8]) {
+    if !options.is_empty() {
+        buf.extend_from_slice(options);
+        push_option(buf, OPT_ENDOFOPT, &[]);
+    }
+}
+
+fn comment_option(comment: &str) -> Result<Vec<u8>, Error> {
+    if comment.len() > usize::from(u16::MAX) {
+        return Err(Error::with_message(-1, "pcapng comment is longer than 65535 bytes"));
+    }
+    let mut options = vec![];
+    push_option(&mut options, OPT_COMMENT, comment.as_bytes());
+    Ok(options)
+}
+
+/// Frame `body` as a block: type, total length, body, total length again
+fn write_block<W: Write>(writer: &mut W, block_type: u32, body: &[u8]) -> Result<(), Error> {
+    let total = (body.len() + 12) as u32;
+    writer.write_all(&block_type.to_ne_bytes())?;
+    writer.write_all(&total.to_ne_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&total.to_ne_bytes())?;
+    Ok(())
+}
+
+/// Writes a pcapng file
+///
+/// The section and interface headers are held back until the first packet
+/// is written, so `set_file_comment` can still be called right after
+/// creating the dumper.
+pub struct PcapNgDumper<W: Write> {
+    writer: W,
+    linktype: i32,
+    snaplen: u32,
+    precision: TstampPrecision,
+    file_comment: Option<String>,
+    started: bool,
+}
+
+impl<W: Write> PcapNgDumper<W> {
+    /// Timestamps are taken to be in microseconds
+    pub fn new(writer: W, linktype: i32, snaplen: u32) -> PcapNgDumper<W> {
+        PcapNgDumper::with_precision(writer, linktype, snaplen, TstampPrecision::Micro)
+    }
+
+    /// The `usec` field of every `TimeStamp` passed to `write` is
+    /// interpreted at `precision`.
+    pub fn with_precision(
+        writer: W,
+        linktype: i32,
+        snaplen: u32,
+        precision: TstampPrecision,
+    ) -> PcapNgDumper<W> {
+        PcapNgDumper {
+            writer,
+            linktype,
+            snaplen,
+            precision,
+            file_comment: None,
+            started: false,
+        }
+    }
+
+    /// Comment on the whole file, stored in the section header. Fails once
+    /// the section header has been written, i.e. after the first packet or
+    /// `flush`.
+    pub fn set_file_comment(&mut self, text: &str) -> Result<(), Error> {
+        if self.started {
+            return Err(Error::with_message(-1, "pcapng section header already written"));
+        }
+        comment_option(text)?;
+        self.file_comment = Some(text.to_owned());
+        Ok(())
+    }
+
+    fn start(&mut self) -> Result<(), Error> {
+        if self.started {
+            return Ok(());
+        }
+
+        let mut shb = vec![];
+        shb.extend_from_slice(&BYTE_ORDER_MAGIC.to_ne_bytes());
+        shb.extend_from_slice(&1u16.to_ne_bytes());
+        shb.extend_from_slice(&0u16.to_ne_bytes());
+        // section length not given
+        shb.extend_from_slice(&(-1i64).to_ne_bytes());
+        if let Some(comment) = &self.file_comment {
+            end_options(&mut shb, &comment_option(comment)?);
+        }
+        write_block(&mut self.writer, BLOCK_SECTION_HEADER, &shb)?;
+
+        let mut idb = vec![];
+        idb.extend_from_slice(&(self.linktype as u16).to_ne_bytes());
+        idb.extend_from_slice(&0u16.to_ne_bytes());
+        idb.extend_from_slice(&self.snaplen.to_ne_bytes());
+        // microseconds are the default resolution
+        if self.precision == TstampPrecision::Nano {
+            let mut options = vec![];
+            push_option(&mut options, IF_TSRESOL, &[9]);
+            end_options(&mut idb, &options);
+        }
+        write_block(&mut self.writer, BLOCK_INTERFACE_DESCRIPTION, &idb)?;
+
+        self.started = true;
+        Ok(())
+    }
+
+    /// At most `data.len()` bytes are written, whatever `header.caplen` says.
+    pub fn write(&mut self, header: &PacketHeader, data: &[u8]) -> Result<(), Error> {
+        self.write_block(header, data, None)
+    }
+
+    /// `write`, with `comment` attached to this packet
+    pub fn write_with_comment(
+        &mut self,
+        header: &PacketHeader,
+        data: &[u8],
+        comment: &str,
+    ) -> Result<(), Error> {
+        self.write_block(header, data, Some(comment))
+    }
+
+    fn write_block(
+        &mut self,
+        header: &PacketHeader,
+        data: &[u8],
+        comment: Option<&str>,
+    ) -> Result<(), Error> {
+        let options = match comment {
+            Some(comment) => comment_option(comment)?,
+            None => vec![],
+        };
+        self.start()?;
+
+        let units_per_sec = match self.precision {
+            TstampPrecision::Micro => 1_000_000,
+            TstampPrecision::Nano => 1_000_000_000,
+        };
+        let ts = (header.ts.sec as u64)
+            .wrapping_mul(units_per_sec)
+            .wrapping_add(header.ts.usec as u64);
+        let caplen = header.caplen.min(data.len() as u32);
+
+        let mut epb = Vec::with_capacity(20 + data.len() + options.len() + 8);
+        epb.extend_from_slice(&0u32.to_ne_bytes());
+        epb.extend_from_slice(&((ts >> 32) as u32).to_ne_bytes());
+        epb.extend_from_slice(&(ts as u32).to_ne_bytes());
+        epb.extend_from_slice(&caplen.to_ne_bytes());
+        epb.extend_from_slice(&header.len.to_ne_bytes());
+        epb.extend_from_slice(&data[..caplen as usize]);
+        pad(&mut epb);
+        end_options(&mut epb, &options);
+        write_block(&mut self.writer, BLOCK_ENHANCED_PACKET, &epb)
+    }
+
+    /// Also writes the section and interface headers if no packet has been
+    /// written yet.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.start()?;
+        Ok(self.writer.flush()?)
+    }
+
+    /// Flush and give back the writer
+    pub fn into_inner(mut self) -> Result<W, Error> {
+        self.flush()?;
+        Ok(self.writer)
+    }
+}
+
+fn u16_at(swapped: bool, buf: &[u8], offset: usize) -> u16 {
+    let value = u16::from_ne_bytes([buf[offset], buf[offset + 1]]);
+    if swapped { value.swap_bytes() } else { value }
+}
+
+/// The options at the end of a block body, up to `opt_endofopt`
+fn parse_options(swapped: bool, mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut options = vec![];
+    while data.len() >= 4 {
+        let code = u16_at(swapped, data, 0);
+        let len = usize::from(u16_at(swapped, data, 2));
+        let value = match data.get(4..4 + len) {
+            Some(value) if code != OPT_ENDOFOPT => value,
+            _ => break,
+        };
+        options.push((code, value));
+        data = data.get(4 + ((len + 3) & !3)..).unwrap_or(&[]);
+    }
+    options
+}
+
+/// Timestamp units per second for an `if_tsresol` value: a power of ten, or
+/// of two if the top bit is set
+fn units_per_sec(tsresol: u8) -> Result<u64, Error> {
+    let units = if tsresol & 0x80 == 0 {
+        10u64.checked_pow(u32::from(tsresol))
+    } else {
+        1u64.checked_shl(u32::from(tsresol & 0x7f)).filter(|_| tsresol & 0x7f < 64)
+    };
+    units.ok_or_else(|| Error::with_message(-1, &format!("unsupported pcapng timestamp resolution {:#x}", tsresol)))
+}
+
+/// An interface described in the current section of a pcapng file
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct Interface {
+    /// link-layer header type (DLT) of the packets captured on it
+    pub linktype: i32,
+    /// 0 if unlimited
+    pub snaplen: u32,
+    /// timestamp units per second, from `if_tsresol`
+    pub units_per_sec: u64,
+    /// seconds added to every timestamp, from `if_tsoffset`
+    pub ts_offset: i64,
+}
+
+/// Reads packets from a pcapng file
+///
+/// Each section may use either byte order, and each interface its own link
+/// type and timestamp resolution; timestamps are converted to the precision
+/// the reader was opened with. Block types other than section headers,
+/// interface descriptions and (enhanced or simple) packets are skipped.
+pub struct PcapNgReader<R> {
+    reader: R,
+    swapped: bool,
+    interfaces: Vec<Interface>,
+    current: Option<usize>,
+    precision: TstampPrecision,
+}
+
+impl<R: Read> PcapNgReader<R> {
+    /// Read the first section header; timestamps come out in microseconds
+    pub fn new(reader: R) -> Result<PcapNgReader<R>, Error> {
+        PcapNgReader::with_precision(reader, TstampPrecision::Micro)
+    }
+
+    /// Read the first section header; the `usec` field of each packet's
+    /// `TimeStamp` is then at `precision`
+    pub fn with_precision(mut reader: R, precision: TstampPrecision) -> Result<PcapNgReader<R>, Error> {
+        let mut block_type = [0u8; 4];
+        if read_full(&mut reader, &mut block_type)? < block_type.len() {
+            return Err(truncated());
+        }
+        if !is_pcapng(&block_type) {
+            return Err(Error::with_message(-1, "not a pcapng file"));
+        }
+        let mut reader = PcapNgReader {
+            reader,
+            swapped: false,
+            interfaces: vec![],
+            current: None,
+            precision,
+        };
+        reader.read_block(block_type)?;
+        Ok(reader)
+    }
+
+    fn fill(&mut self, buf: &mut [u8]) -> Result<(), Error> {
+        if read_full(&mut self.reader, buf)? < buf.len() {
+            return Err(truncated());
+        }
+        Ok(())
+    }
+
+    /// The type and body of the next block, `None` at the end of the file
+    fn next_block(&mut self) -> Result<Option<(u32, Vec<u8>)>, Error> {
+        let mut block_type = [0u8; 4];
+        match read_full(&mut self.reader, &mut block_type)? {
+            0 => Ok(None),
+            4 => self.read_block(block_type).map(Some),
+            _ => Err(truncated()),
+        }
+    }
+
+    /// Read the rest of a block whose type has been read. A section header
+    /// sets the byte order for the blocks after it and forgets the previous
+    /// section's interfaces.
+    fn read_block(&mut self, block_type: [u8; 4]) -> Result<(u32, Vec<u8>), Error> {
+        let mut len = [0u8; 4];
+        self.fill(&mut len)?;
+        let mut body = vec![];
+        if is_pcapng(&block_type) {
+            let mut magic = [0u8; 4];
+            self.fill(&mut magic)?;
+            self.swapped = match u32::from_ne_bytes(magic) {
+                BYTE_ORDER_MAGIC => false,
+                magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                _ => return Err(Error::with_message(-1, "bad pcapng byte order magic")),
+            };
+            self.interfaces.clear();
+            self.current = None;
+            body.extend_from_slice(&magic);
+        }
+
+        let block_type = u32_at(self.swapped, &block_type, 0);
+        let len = u32_at(self.swapped, &len, 0) as usize;
+        if len < 12 + body.len() || len & 3 != 0 || len > MAX_BLOCK_LEN {
+            return Err(Error::with_message(-1, &format!("bad pcapng block length {}", len)));
+        }
+        let start = body.len();
+        body.resize(len - 12, 0);
+        self.fill(&mut body[start..])?;
+        let mut trailer = [0u8; 4];
+        self.fill(&mut trailer)?;
+        if u32_at(self.swapped, &trailer, 0) as usize != len {
+            return Err(Error::with_message(-1, "pcapng block lengths don't match"));
+        }
+
+        if block_type == BLOCK_SECTION_HEADER && (body.len() < 16 || u16_at(self.swapped, &body, 4) != 1) {
+            return Err(Error::with_message(-1, "unsupported pcapng version"));
+        }
+        Ok((block_type, body))
+    }
+
+    fn add_interface(&mut self, body: &[u8]) -> Result<(), Error> {
+        if body.len() < 8 {
+            return Err(truncated());
+        }
+        let mut interface = Interface {
+            linktype: i32::from(u16_at(self.swapped, body, 0)),
+            snaplen: u32_at(self.swapped, body, 4),
+            units_per_sec: 1_000_000,
+            ts_offset: 0,
+        };
+        for (code, value) in parse_options(self.swapped, &body[8..]) {
+            match (code, value.len()) {
+                (IF_TSRESOL, 1) => interface.units_per_sec = units_per_sec(value[0])?,
+                (IF_TSOFFSET, 8) => {
+                    let offset = [value[0], value[1], value[2], value[3], value[4], value[5], value[6], value[7]];
+                    let offset = u64::from_ne_bytes(offset);
+                    interface.ts_offset = if self.swapped { offset.swap_bytes() } else { offset } as i64;
+                }
+                _ => {}
+            }
+        }
+        self.interfaces.push(interface);
+        Ok(())
+    }
+
+    /// `ts` in the interface's units as a `TimeStamp`, failing if
+    /// `if_tsoffset` pushes it out of range
+    fn timestamp(&self, interface: &Interface, ts: u64) -> Result<TimeStamp, Error> {
+        let target: u128 = match self.precision {
+            TstampPrecision::Micro => 1_000_000,
+            TstampPrecision::Nano => 1_000_000_000,
+        };
+        let units = u128::from(interface.units_per_sec);
+        let ts = u128::from(ts);
+        let sec = i64::try_from(ts / units)
+            .ok()
+            .and_then(|sec| sec.checked_add(interface.ts_offset))
+            .ok_or_else(|| Error::with_message(-1, "pcapng timestamp out of range"))?;
+        Ok(TimeStamp {
+            sec,
+            usec: (ts % units * target / units) as i64,
+        })
+    }
+
+    fn interface_at(&self, id: usize) -> Result<&Interface, Error> {
+        self.interfaces.get(id).ok_or_else(|| Error::with_message(
+            -1,
+            &format!("packet from undescribed pcapng interface {}", id),
+        ))
+    }
+
+    /// Read the next packet, `None` at the end of the file. `interface`
+    /// then describes the interface it was captured on.
+    pub fn next_packet(&mut self) -> Result<Option<(PacketHeader, Vec<u8>)>, Error> {
+        loop {
+            let (block_type, mut body) = match self.next_block()? {
+                Some(block) => block,
+                None => return Ok(None),
+            };
+            let (id, header, start) = match block_type {
+                BLOCK_INTERFACE_DESCRIPTION => {
+                    self.add_interface(&body)?;
+                    continue;
+                }
+                BLOCK_ENHANCED_PACKET => {
+                    if body.len() < 20 {
+                        return Err(truncated());
+                    }
+                    let id = u32_at(self.swapped, &body, 0) as usize;
+                    let ts = u64::from(u32_at(self.swapped, &body, 4)) << 32
+                        | u64::from(u32_at(self.swapped, &body, 8));
+                    let caplen = u32_at(self.swapped, &body, 12);
+                    if caplen as usize > body.len() - 20 {
+                        return Err(truncated());
+                    }
+                    let header = PacketHeader {
+                        ts: self.timestamp(self.interface_at(id)?, ts)?,
+                        caplen,
+                        len: u32_at(self.swapped, &body, 16),
+                    };
+                    (id, header, 20)
+                }
+                // no timestamp, and captured on the section's first interface
+                BLOCK_SIMPLE_PACKET => {
+                    if body.len() < 4 {
+                        return Err(truncated());
+                    }
+                    let len = u32_at(self.swapped, &body, 0);
+                    let snaplen = match self.interface_at(0)?.snaplen {
+                        0 => u32::MAX,
+                        snaplen => snaplen,
+                    };
+                    let header = PacketHeader {
+                        ts: TimeStamp { sec: 0, usec: 0 },
+                        caplen: len.min(snaplen).min((body.len() - 4) as u32),
+                        len,
+                    };
+                    (0, header, 4)
+                }
+                _ => continue,
+            };
+            body.truncate(start + header.caplen as usize);
+            body.drain(..start);
+            self.current = Some(id);
+            return Ok(Some((header, body)));
+        }
+    }
+
+    /// The interfaces described so far in the current section
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.interfaces
+    }
+
+    /// The interface the packet last returned was captured on
+    pub fn interface(&self) -> Option<&Interface> {
+        self.interfaces.get(self.current?)
+    }
+
+    /// The link type of the packet last returned
+    pub fn datalink(&self) -> Option<i32> {
+        self.interface().map(|interface| interface.linktype)
+    }
+
+    pub fn tstamp_precision(&self) -> TstampPrecision {
+        self.precision
+    }
+
+    /// Whether the current section was written in the opposite byte order to
+    /// this host
+    pub fn is_swapped(&self) -> bool {
+        self.swapped
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Iterator for PcapNgReader<R> {
+    type Item = Result<(PacketHeader, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::TimeStamp;
+
+    fn u32_at(buf: &[u8], offset: usize) -> u32 {
+        u32::from_ne_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]])
+    }
+
+    #[test]
+    fn writes_comments_into_blocks() {
+        let mut dumper = PcapNgDumper::new(Vec::new(), 1, 65535);
+        dumper.set_file_comment("case 42").unwrap();
+        let header = PacketHeader { ts: TimeStamp { sec: 1, usec: 2 }, caplen: 3, len: 3 };
+        dumper.write_with_comment(&header, &[1, 2, 3], "suspicious").unwrap();
+        assert!(dumper.set_file_comment("too late").is_err());
+        let bytes = dumper.into_inner().unwrap();
+
+        // walk the blocks, checking the framing
+        let mut blocks = vec![];
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let block_type = u32_at(&bytes, offset);
+            let total = u32_at(&bytes, offset + 4) as usize;
+            assert_eq!(total % 4, 0);
+            assert_eq!(u32_at(&bytes, offset + total - 4) as usize, total);
+            blocks.push((block_type, &bytes[offset..offset + total]));
+            offset += total;
+        }
+        let types: Vec<u32> = blocks.iter().map(|block| block.0).collect();
+        assert_eq!(types, [BLOCK_SECTION_HEADER, BLOCK_INTERFACE_DESCRIPTION, BLOCK_ENHANCED_PACKET]);
+
+        let contains = |haystack: &[u8], needle: &[u8]| haystack.windows(needle.len()).any(|w| w == needle);
+        assert!(contains(blocks[0].1, b"case 42"));
+        assert!(contains(blocks[2].1, b"suspicious"));
+        assert_eq!(u32_at(blocks[2].1, 16), 1_000_002);
+        assert!(is_pcapng(&bytes));
+
+        let mut reader = PcapNgReader::new(&bytes[..]).unwrap();
+        let (read_header, data) = reader.next_packet().unwrap().unwrap();
+        assert_eq!(read_header.ts, header.ts);
+        assert_eq!(data, [1, 2, 3]);
+        assert_eq!(reader.datalink(), Some(1));
+        assert!(reader.next_packet().unwrap().is_none());
+    }
+
+    /// A big-endian block
+    fn block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let total = (body.len() + 12) as u32;
+        let mut block = block_type.to_be_bytes().to_vec();
+        block.extend_from_slice(&total.to_be_bytes());
+        block.extend_from_slice(body);
+        block.extend_from_slice(&total.to_be_bytes());
+        block
+    }
+
+    /// A big-endian enhanced packet block
+    fn packet(id: u32, ts: u64, data: &[u8], len: u32) -> Vec<u8> {
+        let mut body = id.to_be_bytes().to_vec();
+        body.extend_from_slice(&((ts >> 32) as u32).to_be_bytes());
+        body.extend_from_slice(&(ts as u32).to_be_bytes());
+        body.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        body.extend_from_slice(&len.to_be_bytes());
+        body.extend_from_slice(data);
+        pad(&mut body);
+        block(BLOCK_ENHANCED_PACKET, &body)
+    }
+
+    /// A big-endian section header block of unknown length
+    fn section_header() -> Vec<u8> {
+        block(BLOCK_SECTION_HEADER, &[
+            0x1a, 0x2b, 0x3c, 0x4d, 0, 1, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+        ])
+    }
+
+    #[test]
+    fn reads_swapped_sections_with_several_interfaces() {
+        let mut file = section_header();
+        // Ethernet with nanosecond timestamps, then Linux cooked
+        file.extend(block(BLOCK_INTERFACE_DESCRIPTION, &[0, 1, 0, 0, 0, 0, 0, 0, 0, 9, 0, 1, 9, 0, 0, 0, 0, 0, 0, 0]));
+        file.extend(block(BLOCK_INTERFACE_DESCRIPTION, &[0, 113, 0, 0, 0, 0, 0xff, 0xff]));
+        file.extend(packet(1, 1_500_000_000_000_005, &[1, 2, 3], 10));
+        file.extend(packet(0, 2_000_000_007, &[4], 1));
+        file.extend(block(0x0bad, &[0; 4]));
+
+        let mut reader = PcapNgReader::new(&file[..]).unwrap();
+        let (header, data) = reader.next_packet().unwrap().unwrap();
+        assert_eq!(header.ts, TimeStamp { sec: 1_500_000_000, usec: 5 });
+        assert_eq!((header.caplen, header.len), (3, 10));
+        assert_eq!(data, [1, 2, 3]);
+        assert_eq!(reader.datalink(), Some(113));
+        assert!(reader.is_swapped() != cfg!(target_endian = "big"));
+
+        let (header, data) = reader.next_packet().unwrap().unwrap();
+        assert_eq!(header.ts, TimeStamp { sec: 2, usec: 0 });
+        assert_eq!(data, [4]);
+        assert_eq!(reader.interface().unwrap().units_per_sec, 1_000_000_000);
+        assert!(reader.next_packet().unwrap().is_none());
+
+        assert!(PcapNgReader::new(&file[..file.len() - 1]).unwrap().nth(2).unwrap().is_err());
+    }
+
+    #[test]
+    fn rejects_timestamp_offsets_out_of_range() {
+        let mut file = section_header();
+        // Ethernet with an if_tsoffset of i64::MAX seconds
+        let mut idb = vec![0, 1, 0, 0, 0, 0, 0, 0, 0, 14, 0, 8];
+        idb.extend_from_slice(&i64::MAX.to_be_bytes());
+        idb.extend_from_slice(&[0, 0, 0, 0]);
+        file.extend(block(BLOCK_INTERFACE_DESCRIPTION, &idb));
+        file.extend(packet(0, 1_000_000, &[1], 1));
+
+        let mut reader = PcapNgReader::new(&file[..]).unwrap();
+        let err = reader.next_packet().unwrap_err();
+        assert_eq!(err.message(), Some("pcapng timestamp out of range"));
+    }
+}
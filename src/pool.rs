@@ -0,0 +1,200 @@
+//! Capturing into a fixed set of reusable buffers
+//!
+//! `Handle::loop_` only lends each packet to the callback, so keeping one
+//! means copying it into a fresh `Vec`. A `PacketPool` allocates all of its
+//! buffers up front instead; each captured packet is copied into a free slot
+//! and handed out as a `PooledPacket` that puts the slot back when dropped.
+#[cfg(feature = "libpcap")]
+use core::{Error, Handle};
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use types::PacketHeader;
+
+struct Slots {
+    free: Mutex<Vec<Box<[u8]>>>,
+    exhausted: AtomicUsize,
+}
+
+/// A fixed number of equally sized packet buffers, shared by every
+/// `PooledPacket` leased from it. Cloning gives another handle to the same
+/// slots.
+#[derive(Clone)]
+pub struct PacketPool {
+    slots: Arc<Slots>,
+    slot_size: usize,
+    capacity: usize,
+}
+
+impl PacketPool {
+    /// Allocate `slots` buffers of `slot_size` bytes each. `slot_size` should
+    /// be at least the snaplen, or packets will be cut short.
+    pub fn new(slots: usize, slot_size: usize) -> PacketPool {
+        let free = (0..slots).map(|_| vec![0u8; slot_size].into_boxed_slice()).collect();
+        PacketPool {
+            slots: Arc::new(Slots {
+                free: Mutex::new(free),
+                exhausted: AtomicUsize::new(0),
+            }),
+            slot_size,
+            capacity: slots,
+        }
+    }
+
+    pub fn slot_size(&self) -> usize {
+        self.slot_size
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Slots not currently leased
+    pub fn available(&self) -> usize {
+        self.slots.free.lock().unwrap().len()
+    }
+
+    /// Packets that couldn't be stored because every slot was leased
+    pub fn exhausted(&self) -> usize {
+        self.slots.exhausted.load(Ordering::Relaxed)
+    }
+
+    /// Copy a packet into a free slot. At most `slot_size` bytes are kept and
+    /// `caplen` is lowered to match. Returns `None`, and counts the packet as
+    /// exhausted, if no slot is free.
+    pub fn lease(&self, mut header: PacketHeader, data: &[u8]) -> Option<PooledPacket> {
+        let buf = self.slots.free.lock().unwrap().pop();
+        let mut buf = match buf {
+            Some(buf) => buf,
+            None => {
+                self.slots.exhausted.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        header.caplen = header.caplen.min(len as u32);
+        Some(PooledPacket {
+            header,
+            buf: Some(buf),
+            len,
+            slots: self.slots.clone(),
+        })
+    }
+}
+
+#[cfg(feature = "libpcap")]
+impl PacketPool {
+    /// `Handle::dispatch`, with each packet leased from the pool. Packets
+    /// arriving while the pool is exhausted are skipped and counted by
+    /// `exhausted`; the returned count includes them.
+    pub fn dispatch<F: FnMut(PooledPacket)>(
+        &self,
+        handle: &Handle,
+        count: i32,
+        mut f: F,
+    ) -> Result<usize, Error> {
+        handle.dispatch(count, |header, data| {
+            if let Some(packet) = self.lease(header, data) {
+                f(packet)
+            }
+        })
+    }
+
+    /// `Handle::loop_`, with each packet leased from the pool. Packets
+    /// arriving while the pool is exhausted are skipped and counted by
+    /// `exhausted`.
+    pub fn loop_<F: FnMut(PooledPacket)>(
+        &self,
+        handle: &Handle,
+        count: i32,
+        mut f: F,
+    ) -> Result<(), Error> {
+        handle.loop_(count, |header, data| {
+            if let Some(packet) = self.lease(header, data) {
+                f(packet)
+            }
+        })
+    }
+}
+
+impl fmt::Debug for PacketPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PacketPool")
+            .field("slot_size", &self.slot_size)
+            .field("capacity", &self.capacity)
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+/// A captured packet stored in a `PacketPool` slot, which is returned to the
+/// pool when this is dropped.
+pub struct PooledPacket {
+    header: PacketHeader,
+    buf: Option<Box<[u8]>>,
+    len: usize,
+    slots: Arc<Slots>,
+}
+
+impl PooledPacket {
+    pub fn header(&self) -> &PacketHeader {
+        &self.header
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match &self.buf {
+            Some(buf) => &buf[..self.len],
+            None => &[],
+        }
+    }
+}
+
+impl Deref for PooledPacket {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.data()
+    }
+}
+
+impl fmt::Debug for PooledPacket {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("PooledPacket")
+            .field("header", &self.header)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl Drop for PooledPacket {
+    fn drop(&mut self) {
+        if let Some(buf) = self.buf.take() {
+            self.slots.free.lock().unwrap().push(buf);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::TimeStamp;
+
+    #[test]
+    fn slots_are_recycled() {
+        let pool = PacketPool::new(2, 4);
+        let header = PacketHeader { ts: TimeStamp { sec: 0, usec: 0 }, caplen: 6, len: 6 };
+
+        let first = pool.lease(header.clone(), &[1, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(&*first, &[1, 2, 3, 4]);
+        assert_eq!(first.header().caplen, 4);
+        let _second = pool.lease(header.clone(), &[7]).unwrap();
+        assert!(pool.lease(header.clone(), &[8]).is_none());
+        assert_eq!(pool.exhausted(), 1);
+
+        drop(first);
+        assert_eq!(pool.available(), 1);
+        assert_eq!(&*pool.lease(header, &[9]).unwrap(), &[9]);
+    }
+}
@@ -0,0 +1,232 @@
+//! Reassembly of fragmented IPv4 datagrams
+use checksum;
+use ip::Ipv4Packet;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::time::{Duration, SystemTime};
+
+/// IPv4 datagrams can't be longer than this, fragmented or not
+const MAX_DATAGRAM_LEN: usize = 65_535;
+
+/// More fragments than a datagram can be split into, at 8 bytes each; a
+/// datagram collecting this many is being fed duplicates and is dropped
+const MAX_FRAGMENTS: usize = MAX_DATAGRAM_LEN / 8 + 1;
+
+/// Fragments belong to the same datagram when all of these match (RFC 791)
+#[derive(Clone,Copy,Debug,Eq,PartialEq,Hash)]
+pub struct FragmentKey {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub identification: u16,
+    pub protocol: u8,
+}
+
+#[derive(Debug)]
+struct PartialDatagram {
+    first_seen: SystemTime,
+    /// the header of the fragment at offset 0, once it has arrived
+    header: Option<Vec<u8>>,
+    /// payload length, known once the fragment without MF has arrived
+    total_len: Option<usize>,
+    fragments: Vec<(usize, Vec<u8>)>,
+}
+
+impl PartialDatagram {
+    fn assemble(&mut self) -> Option<Vec<u8>> {
+        let total_len = self.total_len?;
+        let header = self.header.as_ref()?;
+
+        self.fragments.sort_by_key(|&(offset, _)| offset);
+        let mut covered = 0;
+        for (offset, data) in self.fragments.iter().filter(|&&(offset, _)| offset < total_len) {
+            if *offset > covered {
+                return None;
+            }
+            covered = covered.max(offset + data.len());
+        }
+        if covered < total_len {
+            return None;
+        }
+
+        let mut datagram = header.clone();
+        let header_len = datagram.len();
+        datagram.resize(header_len + total_len, 0);
+        for (offset, data) in self.fragments.iter().filter(|&&(offset, _)| offset < total_len) {
+            let end = (offset + data.len()).min(total_len);
+            datagram[header_len + offset..header_len + end].copy_from_slice(&data[..end - offset]);
+        }
+
+        datagram[2..4].copy_from_slice(&((header_len + total_len) as u16).to_be_bytes());
+        datagram[6] &= 0x40; // keep DF, clear MF and the offset
+        datagram[7] = 0;
+        datagram[10..12].copy_from_slice(&[0, 0]);
+        let checksum = !checksum::fold(checksum::sum_words(&datagram[..header_len]));
+        datagram[10..12].copy_from_slice(&checksum.to_be_bytes());
+        Some(datagram)
+    }
+}
+
+/// Buffers IPv4 fragments until every piece of a datagram has arrived.
+///
+/// Feed it the IP layer of captured packets with `push`. Unfragmented
+/// packets come straight back; fragments are held until the datagram is
+/// complete and then returned as one packet with the fragmentation fields
+/// cleared and the header checksum recomputed. Incomplete datagrams older
+/// than the timeout are dropped on the next `push` or `evict_expired`.
+///
+/// Time is whatever the caller passes in, normally the capture timestamp,
+/// so replaying a savefile behaves the same as the live capture did.
+#[derive(Debug)]
+pub struct Ipv4Reassembler {
+    timeout: Duration,
+    pending: HashMap<FragmentKey, PartialDatagram>,
+}
+
+impl Ipv4Reassembler {
+    pub fn new(timeout: Duration) -> Ipv4Reassembler {
+        Ipv4Reassembler {
+            timeout,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Datagrams still waiting for fragments
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Drop incomplete datagrams first seen more than the timeout before
+    /// `now`, returning how many were dropped.
+    pub fn evict_expired(&mut self, now: SystemTime) -> usize {
+        let timeout = self.timeout;
+        let before = self.pending.len();
+        self.pending.retain(|_, partial| {
+            now.duration_since(partial.first_seen).unwrap_or_default() <= timeout
+        });
+        before - self.pending.len()
+    }
+
+    /// Add a captured packet, starting at its IPv4 header. Returns the whole
+    /// datagram when `packet` isn't a fragment or completes one, `None` while
+    /// fragments are outstanding or if `packet` isn't usable IPv4.
+    pub fn push<'a>(&mut self, now: SystemTime, packet: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+        self.evict_expired(now);
+
+        let ip = Ipv4Packet::new(packet)?;
+        if !ip.more_fragments() && ip.fragment_offset() == 0 {
+            return Some(Cow::Borrowed(packet));
+        }
+        let payload = ip.payload();
+        if ip.is_truncated() || ip.header_len() + ip.fragment_offset() + payload.len() > MAX_DATAGRAM_LEN {
+            return None;
+        }
+
+        let key = FragmentKey {
+            src: ip.src(),
+            dst: ip.dst(),
+            identification: ip.identification(),
+            protocol: ip.protocol(),
+        };
+        let partial = self.pending.entry(key).or_insert_with(|| PartialDatagram {
+            first_seen: now,
+            header: None,
+            total_len: None,
+            fragments: vec![],
+        });
+        let end = ip.fragment_offset() + payload.len();
+        if matches!(partial.total_len, Some(total_len) if end > total_len) {
+            // runs past the end another fragment already declared
+            return None;
+        }
+        let overrun = !ip.more_fragments()
+            && partial.fragments.iter().any(|(offset, data)| offset + data.len() > end);
+        if overrun || partial.fragments.len() >= MAX_FRAGMENTS {
+            // contradictory or endless fragments; give up on the datagram
+            self.pending.remove(&key);
+            return None;
+        }
+        if ip.fragment_offset() == 0 {
+            partial.header = Some(ip.header().to_vec());
+        }
+        if !ip.more_fragments() {
+            partial.total_len = Some(end);
+        }
+        partial.fragments.push((ip.fragment_offset(), payload.to_vec()));
+
+        let datagram = partial.assemble()?;
+        self.pending.remove(&key);
+        Some(Cow::Owned(datagram))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fragment(offset: usize, more: bool, payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![
+            0x45, 0, 0, 0, 0xbe, 0xef, 0, 0, 64, 17, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+        ];
+        let flags_offset = (offset / 8) as u16 | if more { 0x2000 } else { 0 };
+        packet[2..4].copy_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+        packet[6..8].copy_from_slice(&flags_offset.to_be_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    #[test]
+    fn reassembles_out_of_order_fragments() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut reassembler = Ipv4Reassembler::new(Duration::from_secs(30));
+
+        let last = fragment(16, false, b"tail");
+        let first = fragment(0, true, b"01234567");
+        let middle = fragment(8, true, b"89abcdef");
+        assert!(reassembler.push(now, &last).is_none());
+        assert!(reassembler.push(now, &first).is_none());
+        let datagram = reassembler.push(now, &middle).unwrap();
+
+        let ip = Ipv4Packet::new(&datagram).unwrap();
+        assert_eq!(ip.payload(), b"0123456789abcdeftail");
+        assert!(!ip.more_fragments());
+        assert!(checksum::verify_ipv4_checksum(&ip));
+        assert_eq!(reassembler.pending(), 0);
+
+        assert!(reassembler.push(now, &first).is_none());
+        assert_eq!(reassembler.evict_expired(now + Duration::from_secs(31)), 1);
+    }
+
+    #[test]
+    fn drops_contradictory_fragments() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut reassembler = Ipv4Reassembler::new(Duration::from_secs(30));
+
+        // a last fragment ending before fragments already stored
+        for offset in [0, 8, 16, 24] {
+            assert!(reassembler.push(now, &fragment(offset, true, b"01234567")).is_none());
+        }
+        assert!(reassembler.push(now, &fragment(8, false, b"01234567")).is_none());
+        assert_eq!(reassembler.pending(), 0);
+
+        // fragments past the end declared by the last one are ignored
+        assert!(reassembler.push(now, &fragment(8, false, b"89abcdef")).is_none());
+        assert!(reassembler.push(now, &fragment(24, true, b"xxxxxxxx")).is_none());
+        let first = fragment(0, true, b"01234567");
+        let datagram = reassembler.push(now, &first).unwrap();
+        assert_eq!(Ipv4Packet::new(&datagram).unwrap().payload(), b"0123456789abcdef");
+    }
+
+    #[test]
+    fn drops_datagrams_flooded_with_duplicates() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let mut reassembler = Ipv4Reassembler::new(Duration::from_secs(30));
+        let first = fragment(0, true, b"01234567");
+        for _ in 0..MAX_FRAGMENTS {
+            assert!(reassembler.push(now, &first).is_none());
+        }
+        assert_eq!(reassembler.pending(), 1);
+        assert!(reassembler.push(now, &first).is_none());
+        assert_eq!(reassembler.pending(), 0);
+    }
+}
@@ -0,0 +1,182 @@
+//! Recording a capture session to a savefile and replaying it later
+//!
+//! A `Replayer` feeds packets to the same kind of callback `Handle::loop_`
+//! takes, so the processing code under test can't tell a replay from the
+//! live capture it was recorded from.
+use core::{open_offline, Dumper, Error, Handle, PacketHeader, TimeStamp, TstampPrecision};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Writes every packet passed through it to a savefile
+pub struct Recorder {
+    dumper: Dumper,
+}
+
+impl Recorder {
+    /// Create `path` with the link type, snaplen and timestamp precision of
+    /// `handle`
+    pub fn new<P: AsRef<Path>>(handle: &Handle, path: P) -> Result<Recorder, Error> {
+        Ok(Recorder {
+            dumper: handle.dump_open(path)?,
+        })
+    }
+
+    pub fn record(&mut self, header: &PacketHeader, data: &[u8]) {
+        self.dumper.write(header, data)
+    }
+
+    /// `Handle::loop_`, recording each packet before passing it on to `f`
+    pub fn loop_<F: FnMut(PacketHeader, &[u8])>(
+        &mut self,
+        handle: &Handle,
+        count: i32,
+        mut f: F,
+    ) -> Result<(), Error> {
+        let dumper = &mut self.dumper;
+        handle.loop_(count, |header, data| {
+            dumper.write(&header, data);
+            f(header, data)
+        })
+    }
+
+    /// Flush and close the savefile
+    pub fn finish(mut self) -> Result<(), Error> {
+        self.dumper.flush()
+    }
+}
+
+/// Time of `ts` relative to `first`, or zero if it's earlier
+fn offset(first: &TimeStamp, ts: &TimeStamp, precision: TstampPrecision) -> Duration {
+    ts.duration_since_with_precision(first, precision).unwrap_or_default()
+}
+
+/// Plays back a savefile, optionally at the pace it was captured
+pub struct Replayer {
+    handle: Handle,
+    preserve_timing: bool,
+    max_duration: Option<Duration>,
+    rebase_timestamps: bool,
+}
+
+impl Replayer {
+    /// Open a savefile for replay, as fast as possible by default
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Replayer, Error> {
+        Ok(Replayer {
+            handle: open_offline(path)?,
+            preserve_timing: false,
+            max_duration: None,
+            rebase_timestamps: false,
+        })
+    }
+
+    /// Sleep between packets so they arrive with the gaps they were captured
+    /// with. Gaps are measured from the first packet, so time spent in the
+    /// callback doesn't accumulate as drift.
+    pub fn preserve_timing(mut self, preserve_timing: bool) -> Replayer {
+        self.preserve_timing = preserve_timing;
+        self
+    }
+
+    /// Stop at the first packet captured more than `max_duration` after the
+    /// first one, whether or not timing is preserved
+    pub fn max_duration(mut self, max_duration: Duration) -> Replayer {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    /// Rewrite each packet's timestamp to the time the replay started plus
+    /// its offset from the first packet, for consumers that expect recent
+    /// timestamps. The gaps between packets are kept.
+    pub fn rebase_timestamps(mut self, rebase_timestamps: bool) -> Replayer {
+        self.rebase_timestamps = rebase_timestamps;
+        self
+    }
+
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    /// Deliver `count` packets (all of them if `count` is 0 or negative),
+    /// returning how many were delivered
+    pub fn run<F: FnMut(PacketHeader, &[u8])>(&mut self, count: i32, mut f: F) -> Result<usize, Error> {
+        let precision = self.handle.tstamp_precision();
+        let preserve_timing = self.preserve_timing;
+        let max_duration = self.max_duration;
+        let rebase_timestamps = self.rebase_timestamps;
+        let handle = &self.handle;
+        let mut start: Option<(Instant, SystemTime, TimeStamp)> = None;
+        let mut delivered = 0;
+        handle.loop_(count, |mut header, data| {
+            let (started, anchor, first) = start
+                .get_or_insert_with(|| (Instant::now(), SystemTime::now(), header.ts.clone()));
+            let elapsed = offset(first, &header.ts, precision);
+            if matches!(max_duration, Some(max) if elapsed > max) {
+                handle.break_loop();
+                return;
+            }
+            let due = *started + elapsed;
+            let now = Instant::now();
+            if preserve_timing && due > now {
+                thread::sleep(due - now);
+            }
+            if rebase_timestamps {
+                if let Ok(ts) = TimeStamp::from_system_time(*anchor + elapsed, precision) {
+                    header.ts = ts;
+                }
+            }
+            delivered += 1;
+            f(header, data)
+        })?;
+        Ok(delivered)
+    }
+
+    pub fn into_handle(self) -> Handle {
+        self.handle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::TempFile;
+
+    #[test]
+    fn offsets_respect_precision() {
+        let first = TimeStamp { sec: 10, usec: 900_000 };
+        let later = TimeStamp { sec: 11, usec: 100_000 };
+        assert_eq!(offset(&first, &later, TstampPrecision::Micro), Duration::from_millis(200));
+        assert_eq!(
+            offset(&first, &later, TstampPrecision::Nano),
+            Duration::from_nanos(999_200_000)
+        );
+        assert_eq!(offset(&later, &first, TstampPrecision::Micro), Duration::from_secs(0));
+    }
+
+    #[test]
+    #[ignore = "needs a real libpcap to read the savefile"]
+    fn stops_after_max_duration() {
+        let packets = [100, 101, 102, 105]
+            .map(|sec| (PacketHeader { ts: TimeStamp { sec, usec: 0 }, caplen: 1, len: 1 }, [0]));
+        let file = TempFile::pcap("replay.pcap", 1, packets);
+
+        let mut replayer = Replayer::open(file.path()).unwrap().max_duration(Duration::from_secs(2));
+        let mut seen = 0;
+        let delivered = replayer.run(0, |_, _| seen += 1).unwrap();
+        assert_eq!((delivered, seen), (3, 3));
+    }
+
+    #[test]
+    #[ignore = "needs a real libpcap to read the savefile"]
+    fn rebases_timestamps_to_now() {
+        let packets = [(100, 0), (101, 500_000)]
+            .map(|(sec, usec)| (PacketHeader { ts: TimeStamp { sec, usec }, caplen: 1, len: 1 }, [0]));
+        let file = TempFile::pcap("rebase.pcap", 1, packets);
+
+        let mut replayer = Replayer::open(file.path()).unwrap().rebase_timestamps(true);
+        let mut stamps = vec![];
+        replayer.run(0, |header, _| stamps.push(header.ts)).unwrap();
+        assert!(stamps[0].elapsed() < Duration::from_secs(5));
+        assert_eq!(stamps[1].duration_since(&stamps[0]), Some(Duration::from_millis(1500)));
+    }
+}
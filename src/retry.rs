@@ -0,0 +1,231 @@
+//! Keeping a capture alive across transient device failures
+use core::{find_all_devs, open_live, Error, ErrorKind, Handle, PacketHeader};
+use pcap_sys as ffi;
+use std::cmp;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How hard `resilient_loop` tries to bring a failed capture back
+#[derive(Clone,Debug)]
+pub struct RetryPolicy {
+    /// reopen attempts allowed before giving up; reset once packets flow again
+    pub max_retries: u32,
+    /// delay before the first reopen attempt, doubled for each further one
+    pub initial_backoff: Duration,
+    /// upper bound for the delay between attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    fn backoff(&self, attempt: u32) -> Duration {
+        1u32.checked_shl(attempt)
+            .and_then(|factor| self.initial_backoff.checked_mul(factor))
+            .map_or(self.max_backoff, |backoff| backoff.min(self.max_backoff))
+    }
+}
+
+/// Like `Handle::loop_` with no packet limit, but when the capture fails with
+/// a transient error the device is closed and reopened from the handle's
+/// `CaptureConfig`, backing off exponentially between attempts.
+///
+/// `prepare` is called on every reopened handle before capture resumes; use
+/// it to re-apply filters and any other state that isn't part of the
+/// `CaptureConfig`. Its errors are returned as is, since retrying won't fix
+/// a bad filter. So are permanent errors, such as the device no longer
+/// existing, and errors on handles without a `CaptureConfig` (savefiles).
+/// Only read failures of the capture loop and `Error::is_transient` errors
+/// from reopening are retried.
+///
+/// A `LoopBreaker` taken from the original handle only stops capture until
+/// the first reopen.
+pub fn resilient_loop<P, F>(
+    handle: Handle,
+    policy: &RetryPolicy,
+    mut prepare: P,
+    mut f: F,
+) -> Result<(), Error>
+where
+    P: FnMut(&mut Handle) -> Result<(), Error>,
+    F: FnMut(PacketHeader, &[u8]),
+{
+    let mut handle = handle;
+    let mut attempt = 0;
+    loop {
+        let mut delivered = false;
+        let err = match handle.loop_(0, |header, packet| {
+            delivered = true;
+            f(header, packet)
+        }) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        if delivered {
+            attempt = 0;
+        }
+        let config = match handle.config() {
+            Some(config) if loop_failure_is_transient(&err) => config.clone(),
+            _ => return Err(err),
+        };
+        warn!("capture on {} failed, reopening: {}", config.device, err);
+        drop(handle);
+
+        let mut last_err = err;
+        handle = loop {
+            if attempt >= policy.max_retries {
+                return Err(last_err);
+            }
+            thread::sleep(policy.backoff(attempt));
+            attempt += 1;
+            match config.open() {
+                Ok(mut handle) => {
+                    prepare(&mut handle)?;
+                    break handle;
+                }
+                Err(err) if err.is_transient() => last_err = err,
+                Err(err) => return Err(err),
+            }
+        };
+    }
+}
+
+/// Whether `loop_` failing with `err` is worth reopening the device for: a
+/// read failure, which libpcap reports with its generic `PCAP_ERROR`, e.g.
+/// when the adapter went away, or a transient error
+fn loop_failure_is_transient(err: &Error) -> bool {
+    err.is_transient() || err.kind() == ErrorKind::Generic
+}
+
+/// How often `open_live_when_ready` looks at the interface again
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long `open_live_when_ready` waits for a fresh handle to deliver
+/// before reopening it
+const DELIVERY_PROBE: Duration = Duration::from_millis(500);
+
+/// Whether `device` is listed as up and running, i.e. has a carrier or a
+/// connected tunnel. libpcap versions that don't report `RUNNING` never
+/// show a device as ready; use `open_live` there.
+fn device_ready(device: &str) -> bool {
+    find_all_devs()
+        .ok()
+        .and_then(|mut devs| devs.find(|dev| dev.name() == device))
+        .is_some_and(|dev| dev.is_up() && dev.is_running())
+}
+
+/// Whether a packet is waiting on `handle` within `timeout`, without
+/// reading it. Without a selectable descriptor there is no way to tell, and
+/// the handle is taken to be delivering.
+#[cfg(unix)]
+fn delivers_within(handle: &Handle, timeout: Duration) -> bool {
+    let fd = unsafe { ffi::pcap_get_selectable_fd(handle.as_raw()) };
+    if fd < 0 {
+        return true;
+    }
+    let mut pollfd = libc::pollfd { fd, events: libc::POLLIN, revents: 0 };
+    let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as libc::c_int;
+    unsafe { libc::poll(&mut pollfd, 1, timeout_ms) > 0 }
+}
+
+#[cfg(not(unix))]
+fn delivers_within(_handle: &Handle, _timeout: Duration) -> bool {
+    true
+}
+
+/// `open_live` for a device that may still be coming up, e.g. a VPN tunnel
+/// that was just connected.
+///
+/// libpcap can open an interface that is up but not yet running, or a
+/// tunnel already flagged as running that isn't passing traffic yet, and
+/// then deliver nothing. This waits, for at most `max_wait`, until the
+/// device is listed as both up and running and opening it succeeds,
+/// retrying transient open failures. The new handle is then given half a
+/// second to have a packet waiting (checked without reading it, on Unix)
+/// and reopened if it doesn't. A device that is merely quiet never passes
+/// that probe, so when the time runs out the last handle opened is returned
+/// anyway.
+///
+/// Permanent open failures are returned straight away. If the time runs out
+/// without a handle, the last open error is returned, or an
+/// `ErrorKind::IfaceNotUp` error if the device never became ready.
+pub fn open_live_when_ready(
+    device: &str,
+    snaplen: i32,
+    promisc: bool,
+    read_timeout_ms: i32,
+    max_wait: Duration,
+) -> Result<Handle, Error> {
+    let deadline = Instant::now() + max_wait;
+    let mut last_err = None;
+    loop {
+        if device_ready(device) {
+            match open_live(device, snaplen, promisc, read_timeout_ms) {
+                Ok(handle) => {
+                    let probe = cmp::min(DELIVERY_PROBE, deadline.saturating_duration_since(Instant::now()));
+                    if delivers_within(&handle, probe) || Instant::now() >= deadline {
+                        return Ok(handle);
+                    }
+                    // opened too early, perhaps; try a fresh handle
+                    continue;
+                }
+                Err(err) if err.is_transient() => last_err = Some(err),
+                Err(err) => return Err(err),
+            }
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(last_err.unwrap_or_else(|| Error::with_message(
+                ffi::PCAP_ERROR_IFACE_NOT_UP,
+                &format!("{} was not up and running within {:?}", device, max_wait),
+            )));
+        }
+        thread::sleep(cmp::min(READY_POLL_INTERVAL, deadline - now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy {
+            max_retries: 40,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(3), Duration::from_millis(800));
+        assert_eq!(policy.backoff(4), Duration::from_secs(1));
+        assert_eq!(policy.backoff(39), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn only_read_failures_and_interfaces_not_up_are_transient() {
+        let generic = Error::with_message(ffi::PCAP_ERROR, "read error");
+        let not_up = Error::with_message(ffi::PCAP_ERROR_IFACE_NOT_UP, "down");
+        let io = Error::from(std::io::Error::other("disk full"));
+        let denied = Error::with_message(ffi::PCAP_ERROR_PERM_DENIED, "denied");
+        assert!(not_up.is_transient());
+        assert!(!generic.is_transient() && !io.is_transient() && !denied.is_transient());
+        assert!(loop_failure_is_transient(&generic) && loop_failure_is_transient(&not_up));
+        assert!(!loop_failure_is_transient(&denied));
+    }
+
+    #[test]
+    fn gives_up_on_a_device_that_never_appears() {
+        let started = Instant::now();
+        let result = open_live_when_ready("rustcap-no-such-dev0", 65535, false, 100, Duration::from_millis(250));
+        assert_eq!(result.err().map(|err| err.kind()), Some(ErrorKind::IfaceNotUp));
+        assert!(started.elapsed() >= Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,780 @@
+//! Pure-Rust reader and writer for the classic libpcap savefile format
+//!
+//! These don't need libpcap, so they can be used to produce and consume
+//! capture files in tools and tests that can't link native code.
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "tokio")]
+use std::future::Future;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+#[cfg(feature = "tokio")]
+use tokio::io::AsyncWrite;
+#[cfg(feature = "sha2")]
+use sha2::{Digest, Sha256};
+use link::fcs_len;
+use types::{Error, PacketHeader, TimeStamp, TstampPrecision, ERROR_TRUNCATED_FILE, MAX_SNAPLEN};
+
+const MAGIC_MICRO: u32 = 0xa1b2_c3d4;
+const MAGIC_NANO: u32 = 0xa1b2_3c4d;
+const VERSION_MAJOR: u16 = 2;
+const VERSION_MINOR: u16 = 4;
+const FILE_HEADER_LEN: usize = 24;
+const RECORD_HEADER_LEN: usize = 16;
+
+pub(crate) fn truncated() -> Error {
+    truncated_in("savefile is truncated")
+}
+
+/// An `ErrorKind::TruncatedFile` error saying where the file ends
+pub(crate) fn truncated_in(message: &str) -> Error {
+    Error::with_message(ERROR_TRUNCATED_FILE, message)
+}
+
+/// Records longer than `MAX_SNAPLEN` are rejected before anything is
+/// allocated for them. libpcap bounds them the same way; the file's own
+/// snaplen can't be trusted for this, since a crafted header could claim
+/// up to 4 GiB.
+fn check_caplen(caplen: u32) -> Result<(), Error> {
+    if caplen > MAX_SNAPLEN {
+        return Err(Error::with_message(-1, &format!(
+            "record length {} is larger than the maximum of {}",
+            caplen, MAX_SNAPLEN
+        )));
+    }
+    Ok(())
+}
+
+/// Fill `buf` from `reader`, returning how much was read before end of input
+pub(crate) fn read_full<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// The global header at the start of a savefile, in host byte order
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct FileHeader {
+    /// identifies the timestamp precision; 0xa1b2c3d4 for micro-, 0xa1b23c4d
+    /// for nanoseconds
+    pub magic: u32,
+    pub version_major: u16,
+    pub version_minor: u16,
+    /// GMT offset of the timestamps, in practice always 0
+    pub thiszone: i32,
+    /// accuracy of the timestamps, in practice always 0
+    pub sigfigs: u32,
+    pub snaplen: u32,
+    /// the link-layer header type (DLT)
+    pub linktype: i32,
+}
+
+impl FileHeader {
+    /// A header as `pcap_dump_open` would write it
+    pub fn new(linktype: i32, snaplen: u32, precision: TstampPrecision) -> FileHeader {
+        FileHeader {
+            magic: match precision {
+                TstampPrecision::Micro => MAGIC_MICRO,
+                TstampPrecision::Nano => MAGIC_NANO,
+            },
+            version_major: VERSION_MAJOR,
+            version_minor: VERSION_MINOR,
+            thiszone: 0,
+            sigfigs: 0,
+            snaplen,
+            linktype,
+        }
+    }
+
+    /// Bytes of frame check sequence at the end of each packet, if the
+    /// file records it in the upper bits of `linktype`; see `link::fcs_len`
+    pub fn fcs_len(&self) -> Option<usize> {
+        fcs_len(self.linktype)
+    }
+
+    /// `None` if the magic number isn't one of the two known values
+    pub fn tstamp_precision(&self) -> Option<TstampPrecision> {
+        match self.magic {
+            MAGIC_MICRO => Some(TstampPrecision::Micro),
+            MAGIC_NANO => Some(TstampPrecision::Nano),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) fn u32_at(swapped: bool, buf: &[u8], offset: usize) -> u32 {
+    let value = u32::from_ne_bytes([buf[offset], buf[offset + 1], buf[offset + 2], buf[offset + 3]]);
+    if swapped {
+        value.swap_bytes()
+    } else {
+        value
+    }
+}
+
+/// Byte order of a savefile
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+impl ByteOrder {
+    /// That of this host
+    pub fn native() -> ByteOrder {
+        if cfg!(target_endian = "big") {
+            ByteOrder::BigEndian
+        } else {
+            ByteOrder::LittleEndian
+        }
+    }
+}
+
+/// Reads packets from a savefile in either byte order and either timestamp
+/// precision.
+pub struct PcapReader<R> {
+    reader: R,
+    swapped: bool,
+    header: FileHeader,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Read and validate the file header
+    pub fn new(reader: R) -> Result<PcapReader<R>, Error> {
+        PcapReader::read_header(reader, None)
+    }
+
+    /// Read the file header in `byte_order`, whatever the magic number says
+    /// and even if it isn't a savefile magic number at all. For testing
+    /// byte order handling, and for salvaging files whose magic number was
+    /// damaged; timestamps are taken to be microseconds unless the magic
+    /// number says otherwise.
+    pub fn with_byte_order(reader: R, byte_order: ByteOrder) -> Result<PcapReader<R>, Error> {
+        PcapReader::read_header(reader, Some(byte_order))
+    }
+
+    fn read_header(mut reader: R, byte_order: Option<ByteOrder>) -> Result<PcapReader<R>, Error> {
+        let mut buf = [0u8; FILE_HEADER_LEN];
+        if read_full(&mut reader, &mut buf)? < FILE_HEADER_LEN {
+            return Err(truncated());
+        }
+
+        let magic = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        let swapped = match (byte_order, magic) {
+            (Some(byte_order), _) => byte_order != ByteOrder::native(),
+            (None, MAGIC_MICRO) | (None, MAGIC_NANO) => false,
+            _ if magic.swap_bytes() == MAGIC_MICRO || magic.swap_bytes() == MAGIC_NANO => true,
+            _ => return Err(Error::with_message(-1, "not a pcap savefile")),
+        };
+
+        let u16_at = |offset: usize| {
+            let value = u16::from_ne_bytes([buf[offset], buf[offset + 1]]);
+            if swapped { value.swap_bytes() } else { value }
+        };
+        let header = FileHeader {
+            magic: u32_at(swapped, &buf, 0),
+            version_major: u16_at(4),
+            version_minor: u16_at(6),
+            thiszone: u32_at(swapped, &buf, 8) as i32,
+            sigfigs: u32_at(swapped, &buf, 12),
+            snaplen: u32_at(swapped, &buf, 16),
+            linktype: u32_at(swapped, &buf, 20) as i32,
+        };
+        Ok(PcapReader { reader, swapped, header })
+    }
+
+    fn u32_at(&self, buf: &[u8], offset: usize) -> u32 {
+        u32_at(self.swapped, buf, offset)
+    }
+
+    /// The file's global header
+    pub fn header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    /// The link-layer header type (DLT) of the file
+    pub fn datalink(&self) -> i32 {
+        self.header.linktype
+    }
+
+    pub fn snaplen(&self) -> u32 {
+        self.header.snaplen
+    }
+
+    pub fn tstamp_precision(&self) -> TstampPrecision {
+        self.header.tstamp_precision().unwrap_or(TstampPrecision::Micro)
+    }
+
+    /// Whether the file was written in the opposite byte order to this host
+    pub fn is_swapped(&self) -> bool {
+        self.swapped
+    }
+
+    /// Read the next record. `None` only if the file ends exactly after the
+    /// previous record; a file that ends partway through one fails with
+    /// `ErrorKind::TruncatedFile`.
+    pub fn next_packet(&mut self) -> Result<Option<(PacketHeader, Vec<u8>)>, Error> {
+        let mut buf = [0u8; RECORD_HEADER_LEN];
+        match read_full(&mut self.reader, &mut buf)? {
+            0 => return Ok(None),
+            RECORD_HEADER_LEN => {}
+            read => return Err(truncated_in(&format!(
+                "savefile ends {} bytes into a {}-byte record header", read, RECORD_HEADER_LEN
+            ))),
+        }
+
+        let header = PacketHeader {
+            ts: TimeStamp {
+                sec: self.u32_at(&buf, 0) as i64,
+                usec: self.u32_at(&buf, 4) as i64,
+            },
+            caplen: self.u32_at(&buf, 8),
+            len: self.u32_at(&buf, 12),
+        };
+        // libpcap accepts records up to MAX_SNAPLEN even if the file's
+        // snaplen is smaller
+        check_caplen(header.caplen)?;
+
+        let mut data = vec![0u8; header.caplen as usize];
+        let read = read_full(&mut self.reader, &mut data)?;
+        if read < data.len() {
+            return Err(truncated_in(&format!(
+                "savefile ends {} bytes into a {}-byte packet", read, data.len()
+            )));
+        }
+        Ok(Some((header, data)))
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read + Seek> PcapReader<R> {
+    /// Count the records from the current position to the end of the file,
+    /// then seek back, so reading continues where it would have anyway.
+    ///
+    /// Only the record headers are read; payloads are skipped by seeking over
+    /// them, so this costs one small read per packet rather than reading the
+    /// whole file.
+    pub fn count_packets(&mut self) -> Result<u64, Error> {
+        let start = self.reader.stream_position()?;
+        let end = self.reader.seek(SeekFrom::End(0))?;
+        self.reader.seek(SeekFrom::Start(start))?;
+
+        let result = self.count_records(start, end);
+        self.reader.seek(SeekFrom::Start(start))?;
+        result
+    }
+
+    fn count_records(&mut self, mut position: u64, end: u64) -> Result<u64, Error> {
+        let mut count = 0;
+        let mut buf = [0u8; RECORD_HEADER_LEN];
+        while position < end {
+            if read_full(&mut self.reader, &mut buf)? < RECORD_HEADER_LEN {
+                return Err(truncated());
+            }
+            let caplen = self.u32_at(&buf, 8);
+            check_caplen(caplen)?;
+            position += (RECORD_HEADER_LEN as u64) + u64::from(caplen);
+            if position > end {
+                return Err(truncated());
+            }
+            self.reader.seek(SeekFrom::Current(i64::from(caplen)))?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = Result<(PacketHeader, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+fn open_file(path: &Path) -> Result<PcapReader<BufReader<File>>, Error> {
+    let in_file = |err: Error| Error::with_message(
+        err.code(),
+        &format!("{}: {}", path.display(), err.message().unwrap_or("read failed")),
+    );
+    let file = File::open(path).map_err(|err| in_file(err.into()))?;
+    PcapReader::new(BufReader::new(file)).map_err(in_file)
+}
+
+/// Reads several savefiles one after the other as a single stream, e.g. the
+/// pieces of a rotated capture
+///
+/// Every file's header is checked when the reader is opened, so a file with a
+/// different link type or timestamp precision is caught before any packet is
+/// read. Files are then opened one at a time as the previous one runs out.
+pub struct MultiFileReader {
+    paths: VecDeque<PathBuf>,
+    current: Option<PcapReader<BufReader<File>>>,
+    header: FileHeader,
+}
+
+impl MultiFileReader {
+    /// Check the headers of `paths`, which are read in the order given
+    pub fn open<I>(paths: I) -> Result<MultiFileReader, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        let paths: VecDeque<PathBuf> = paths.into_iter().map(|path| path.as_ref().to_owned()).collect();
+        let first = match paths.front() {
+            Some(first) => open_file(first)?,
+            None => return Err(Error::with_message(-1, "no savefiles given")),
+        };
+        let header = first.header().clone();
+        for path in paths.iter().skip(1) {
+            let reader = open_file(path)?;
+            if reader.datalink() != header.linktype {
+                return Err(Error::with_message(-1, &format!(
+                    "{}: link type {} doesn't match {}",
+                    path.display(), reader.datalink(), header.linktype
+                )));
+            }
+            if reader.header().tstamp_precision() != header.tstamp_precision() {
+                return Err(Error::with_message(-1, &format!(
+                    "{}: timestamp precision doesn't match the first file",
+                    path.display()
+                )));
+            }
+        }
+        Ok(MultiFileReader { paths, current: None, header })
+    }
+
+    /// The header of the first file; the others share its link type and
+    /// timestamp precision
+    pub fn header(&self) -> &FileHeader {
+        &self.header
+    }
+
+    pub fn datalink(&self) -> i32 {
+        self.header.linktype
+    }
+
+    pub fn tstamp_precision(&self) -> TstampPrecision {
+        self.header.tstamp_precision().unwrap_or(TstampPrecision::Micro)
+    }
+
+    /// The file packets are currently being read from
+    pub fn current_path(&self) -> Option<&Path> {
+        match self.current {
+            Some(_) => self.paths.front().map(AsRef::as_ref),
+            None => None,
+        }
+    }
+
+    /// Read the next record, moving on to the next file at the end of each
+    /// one; `None` once every file is exhausted
+    pub fn next_packet(&mut self) -> Result<Option<(PacketHeader, Vec<u8>)>, Error> {
+        loop {
+            if self.current.is_none() {
+                match self.paths.front() {
+                    Some(path) => self.current = Some(open_file(path)?),
+                    None => return Ok(None),
+                }
+            }
+            if let Some(reader) = &mut self.current {
+                if let Some(packet) = reader.next_packet()? {
+                    return Ok(Some(packet));
+                }
+            }
+            self.current = None;
+            self.paths.pop_front();
+        }
+    }
+}
+
+impl Iterator for MultiFileReader {
+    type Item = Result<(PacketHeader, Vec<u8>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_packet().transpose()
+    }
+}
+
+fn encode_file_header(header: &FileHeader) -> Result<[u8; FILE_HEADER_LEN], Error> {
+    if header.tstamp_precision().is_none() {
+        return Err(Error::with_message(-1, "unknown savefile magic number"));
+    }
+    let mut buf = [0u8; FILE_HEADER_LEN];
+    buf[0..4].copy_from_slice(&header.magic.to_ne_bytes());
+    buf[4..6].copy_from_slice(&header.version_major.to_ne_bytes());
+    buf[6..8].copy_from_slice(&header.version_minor.to_ne_bytes());
+    buf[8..12].copy_from_slice(&header.thiszone.to_ne_bytes());
+    buf[12..16].copy_from_slice(&header.sigfigs.to_ne_bytes());
+    buf[16..20].copy_from_slice(&header.snaplen.to_ne_bytes());
+    buf[20..24].copy_from_slice(&(header.linktype as u32).to_ne_bytes());
+    Ok(buf)
+}
+
+/// The record header and the part of `data` to write after it
+fn encode_record<'a>(header: &PacketHeader, data: &'a [u8]) -> ([u8; RECORD_HEADER_LEN], &'a [u8]) {
+    let caplen = header.caplen.min(data.len() as u32);
+    let mut buf = [0u8; RECORD_HEADER_LEN];
+    buf[0..4].copy_from_slice(&(header.ts.sec as u32).to_ne_bytes());
+    buf[4..8].copy_from_slice(&(header.ts.usec as u32).to_ne_bytes());
+    buf[8..12].copy_from_slice(&caplen.to_ne_bytes());
+    buf[12..16].copy_from_slice(&header.len.to_ne_bytes());
+    (buf, &data[..caplen as usize])
+}
+
+/// Writes a savefile in host byte order, as `pcap_dump` does
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Write a microsecond-precision file header
+    pub fn new(writer: W, linktype: i32, snaplen: u32) -> Result<PcapWriter<W>, Error> {
+        PcapWriter::with_precision(writer, linktype, snaplen, TstampPrecision::Micro)
+    }
+
+    /// Write the file header; the `usec` field of every `TimeStamp` passed to
+    /// `write` is then interpreted at `precision`.
+    pub fn with_precision(
+        writer: W,
+        linktype: i32,
+        snaplen: u32,
+        precision: TstampPrecision,
+    ) -> Result<PcapWriter<W>, Error> {
+        PcapWriter::with_header(writer, &FileHeader::new(linktype, snaplen, precision))
+    }
+
+    /// Write `header` exactly as given, e.g. one taken from a `PcapReader`
+    /// to re-emit a file faithfully.
+    pub fn with_header(mut writer: W, header: &FileHeader) -> Result<PcapWriter<W>, Error> {
+        writer.write_all(&encode_file_header(header)?)?;
+        Ok(PcapWriter { writer })
+    }
+
+    /// At most `data.len()` bytes are written, whatever `header.caplen` says.
+    pub fn write(&mut self, header: &PacketHeader, data: &[u8]) -> Result<(), Error> {
+        let (buf, data) = encode_record(header, data);
+        self.writer.write_all(&buf)?;
+        self.writer.write_all(data)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        Ok(self.writer.flush()?)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// `PcapWriter` for a tokio `AsyncWrite`, so archiving a capture doesn't
+/// tie up a runtime thread on disk I/O. Produces exactly the same bytes.
+///
+/// The file header and each record are encoded into an internal buffer as
+/// soon as they're given, and the returned future writes out everything
+/// buffered so far. Dropping that future early loses nothing: the rest goes
+/// out with the next `write` or `flush`.
+#[cfg(feature = "tokio")]
+pub struct AsyncDumper<W> {
+    writer: W,
+    buf: Vec<u8>,
+    written: usize,
+}
+
+#[cfg(feature = "tokio")]
+impl<W: AsyncWrite + Unpin> AsyncDumper<W> {
+    /// Buffer a microsecond-precision file header
+    pub fn new(writer: W, linktype: i32, snaplen: u32) -> Result<AsyncDumper<W>, Error> {
+        AsyncDumper::with_precision(writer, linktype, snaplen, TstampPrecision::Micro)
+    }
+
+    /// Buffer the file header; the `usec` field of every `TimeStamp` passed
+    /// to `write` is then interpreted at `precision`.
+    pub fn with_precision(
+        writer: W,
+        linktype: i32,
+        snaplen: u32,
+        precision: TstampPrecision,
+    ) -> Result<AsyncDumper<W>, Error> {
+        AsyncDumper::with_header(writer, &FileHeader::new(linktype, snaplen, precision))
+    }
+
+    /// Buffer `header` exactly as given
+    pub fn with_header(writer: W, header: &FileHeader) -> Result<AsyncDumper<W>, Error> {
+        Ok(AsyncDumper {
+            writer,
+            buf: encode_file_header(header)?.to_vec(),
+            written: 0,
+        })
+    }
+
+    /// At most `data.len()` bytes are written, whatever `header.caplen` says.
+    pub fn write<'a>(&'a mut self, header: &PacketHeader, data: &[u8]) -> AsyncDump<'a, W> {
+        let (buf, data) = encode_record(header, data);
+        self.buf.extend_from_slice(&buf);
+        self.buf.extend_from_slice(data);
+        AsyncDump { dumper: self, flush: false }
+    }
+
+    /// Write anything still buffered and flush the writer
+    pub fn flush<'a>(&'a mut self) -> AsyncDump<'a, W> {
+        AsyncDump { dumper: self, flush: true }
+    }
+
+    fn poll_drain(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while self.written < self.buf.len() {
+            match Pin::new(&mut self.writer).poll_write(cx, &self.buf[self.written..]) {
+                Poll::Ready(Ok(0)) => return Poll::Ready(Err(io::ErrorKind::WriteZero.into())),
+                Poll::Ready(Ok(n)) => self.written += n,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        self.buf.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    /// The writer; anything not yet written out is discarded
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Returned by `AsyncDumper::write` and `AsyncDumper::flush`
+#[cfg(feature = "tokio")]
+pub struct AsyncDump<'a, W> {
+    dumper: &'a mut AsyncDumper<W>,
+    flush: bool,
+}
+
+#[cfg(feature = "tokio")]
+impl<'a, W: AsyncWrite + Unpin> Future for AsyncDump<'a, W> {
+    type Output = Result<(), Error>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Error>> {
+        let flush = self.flush;
+        let dumper = &mut *self.dumper;
+        match dumper.poll_drain(cx) {
+            Poll::Ready(Ok(())) if flush => Pin::new(&mut dumper.writer).poll_flush(cx).map_err(Error::from),
+            poll => poll.map_err(Error::from),
+        }
+    }
+}
+
+/// Passes writes through, hashing exactly the bytes that were accepted
+#[cfg(feature = "sha2")]
+struct HashingWriter<W> {
+    writer: W,
+    digest: Sha256,
+}
+
+#[cfg(feature = "sha2")]
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.writer.write(buf)?;
+        self.digest.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// `PcapWriter` that keeps a running SHA-256 of every byte it writes, file
+/// header included, so the digest of a capture can be recorded without
+/// reading the file back.
+///
+/// libpcap's `Dumper` writes through its own `FILE` and can't be hashed
+/// this way; write the file with this instead.
+#[cfg(feature = "sha2")]
+pub struct HashingDumper<W: Write> {
+    writer: PcapWriter<HashingWriter<W>>,
+}
+
+#[cfg(feature = "sha2")]
+impl<W: Write> HashingDumper<W> {
+    pub fn new(writer: W, linktype: i32, snaplen: u32) -> Result<HashingDumper<W>, Error> {
+        HashingDumper::with_precision(writer, linktype, snaplen, TstampPrecision::Micro)
+    }
+
+    pub fn with_precision(
+        writer: W,
+        linktype: i32,
+        snaplen: u32,
+        precision: TstampPrecision,
+    ) -> Result<HashingDumper<W>, Error> {
+        HashingDumper::with_header(writer, &FileHeader::new(linktype, snaplen, precision))
+    }
+
+    pub fn with_header(writer: W, header: &FileHeader) -> Result<HashingDumper<W>, Error> {
+        let writer = HashingWriter { writer, digest: Sha256::new() };
+        Ok(HashingDumper { writer: PcapWriter::with_header(writer, header)? })
+    }
+
+    pub fn write(&mut self, header: &PacketHeader, data: &[u8]) -> Result<(), Error> {
+        self.writer.write(header, data)
+    }
+
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.writer.flush()
+    }
+
+    /// Flush and close the writer, returning the SHA-256 of everything
+    /// written
+    pub fn finalize(mut self) -> Result<[u8; 32], Error> {
+        self.writer.flush()?;
+        Ok(self.writer.into_inner().digest.finalize().into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testutil::TempFile;
+    use types::ErrorKind;
+
+    #[test]
+    fn round_trips_packets() {
+        let mut writer = PcapWriter::with_precision(Vec::new(), 1, 65535, TstampPrecision::Nano).unwrap();
+        let header = PacketHeader {
+            ts: TimeStamp { sec: 1_500_000_000, usec: 999_999_999 },
+            caplen: 4,
+            len: 60,
+        };
+        writer.write(&header, &[1, 2, 3, 4]).unwrap();
+        let bytes = writer.into_inner();
+
+        let mut reader = PcapReader::new(&bytes[..]).unwrap();
+        assert_eq!(reader.datalink(), 1);
+        assert_eq!(reader.snaplen(), 65535);
+        assert_eq!(reader.tstamp_precision(), TstampPrecision::Nano);
+        assert_eq!(*reader.header(), FileHeader::new(1, 65535, TstampPrecision::Nano));
+        let (read_header, data) = reader.next_packet().unwrap().unwrap();
+        assert_eq!(read_header.ts, header.ts);
+        assert_eq!((read_header.caplen, read_header.len), (4, 60));
+        assert_eq!(data, [1, 2, 3, 4]);
+        assert!(reader.next_packet().unwrap().is_none());
+
+        let next = |bytes: &[u8]| {
+            PcapReader::new(bytes).unwrap().next_packet().map(|packet| packet.is_some()).map_err(|err| err.kind())
+        };
+        assert_eq!(next(&bytes[..bytes.len() - 1]), Err(ErrorKind::TruncatedFile));
+        assert_eq!(next(&bytes[..FILE_HEADER_LEN + 3]), Err(ErrorKind::TruncatedFile));
+        assert_eq!(next(&bytes[..FILE_HEADER_LEN]), Ok(false));
+
+        let mut reader = PcapReader::new(io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(reader.count_packets().unwrap(), 1);
+        assert!(reader.next_packet().unwrap().is_some());
+        assert_eq!(reader.count_packets().unwrap(), 0);
+        let mut reader = PcapReader::new(io::Cursor::new(&bytes[..bytes.len() - 1])).unwrap();
+        assert!(reader.count_packets().is_err());
+    }
+
+    #[test]
+    fn rejects_records_longer_than_max_snaplen() {
+        // the header's snaplen doesn't raise the limit
+        let mut writer = PcapWriter::new(Vec::new(), 1, u32::MAX).unwrap();
+        let header = PacketHeader { ts: TimeStamp { sec: 1, usec: 0 }, caplen: 1, len: 1 };
+        writer.write(&header, &[7]).unwrap();
+        let mut bytes = writer.into_inner();
+        let caplen = FILE_HEADER_LEN + 8;
+        bytes[caplen..caplen + 4].copy_from_slice(&(MAX_SNAPLEN + 1).to_ne_bytes());
+
+        assert!(PcapReader::new(&bytes[..]).unwrap().next_packet().is_err());
+        assert!(PcapReader::new(io::Cursor::new(&bytes)).unwrap().count_packets().is_err());
+    }
+
+    #[test]
+    fn byte_order_can_be_forced() {
+        let mut writer = PcapWriter::new(Vec::new(), 1, 65535).unwrap();
+        let header = PacketHeader { ts: TimeStamp { sec: 1, usec: 0 }, caplen: 1, len: 1 };
+        writer.write(&header, &[7]).unwrap();
+        let mut bytes = writer.into_inner();
+        bytes[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        assert!(PcapReader::new(&bytes[..]).is_err());
+
+        let mut reader = PcapReader::with_byte_order(&bytes[..], ByteOrder::native()).unwrap();
+        assert!(!reader.is_swapped());
+        assert_eq!(reader.datalink(), 1);
+        assert_eq!(reader.next_packet().unwrap().unwrap().1, [7]);
+
+        let swapped = match ByteOrder::native() {
+            ByteOrder::LittleEndian => ByteOrder::BigEndian,
+            ByteOrder::BigEndian => ByteOrder::LittleEndian,
+        };
+        let reader = PcapReader::with_byte_order(&bytes[..], swapped).unwrap();
+        assert!(reader.is_swapped());
+        assert_eq!(reader.datalink(), 1i32.swap_bytes());
+    }
+
+    #[test]
+    fn multi_file_reader_chains_files() {
+        let packet = |data: &'static [u8]| {
+            let len = data.len() as u32;
+            (PacketHeader { ts: TimeStamp { sec: 0, usec: 0 }, caplen: len, len }, data)
+        };
+        let first = TempFile::pcap("multi-0.pcap", 1, vec![packet(&[1]), packet(&[2])]);
+        let empty = TempFile::pcap("multi-1.pcap", 1, Vec::<(PacketHeader, &[u8])>::new());
+        let last = TempFile::pcap("multi-2.pcap", 1, vec![packet(&[3])]);
+        let other = TempFile::pcap("multi-3.pcap", 113, vec![packet(&[4])]);
+
+        let reader = MultiFileReader::open(&[first.path(), empty.path(), last.path()]).unwrap();
+        let data: Vec<Vec<u8>> = reader.map(|packet| packet.unwrap().1).collect();
+        assert_eq!(data, [[1], [2], [3]]);
+
+        assert!(MultiFileReader::open(&[first.path(), other.path()]).is_err());
+        assert!(MultiFileReader::open(Vec::<PathBuf>::new()).is_err());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_dumper_matches_pcap_writer() {
+        use std::pin::pin;
+        use std::task::Waker;
+
+        // writing to a Vec never has to wait
+        fn ready<F: Future>(future: F) -> F::Output {
+            match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+                Poll::Ready(output) => output,
+                Poll::Pending => panic!("pending"),
+            }
+        }
+
+        let header = PacketHeader { ts: TimeStamp { sec: 1, usec: 2 }, caplen: 3, len: 3 };
+        let mut writer = PcapWriter::new(Vec::new(), 1, 65535).unwrap();
+        writer.write(&header, &[1, 2, 3]).unwrap();
+        let mut dumper = AsyncDumper::new(Vec::new(), 1, 65535).unwrap();
+        ready(dumper.write(&header, &[1, 2, 3])).unwrap();
+
+        assert_eq!(dumper.into_inner(), writer.into_inner());
+    }
+
+    #[cfg(feature = "sha2")]
+    #[test]
+    fn hashing_dumper_hashes_the_file() {
+        let header = PacketHeader { ts: TimeStamp { sec: 1, usec: 2 }, caplen: 3, len: 3 };
+        let mut file = Vec::new();
+        let mut dumper = HashingDumper::new(&mut file, 1, 65535).unwrap();
+        dumper.write(&header, &[1, 2, 3]).unwrap();
+        dumper.write(&header, &[4, 5, 6]).unwrap();
+        let digest = dumper.finalize().unwrap();
+
+        assert_eq!(file.len(), 24 + 2 * 19);
+        assert_eq!(digest, <[u8; 32]>::from(Sha256::digest(&file)));
+    }
+}
@@ -0,0 +1,132 @@
+//! Widening the snaplen of a live capture when packets keep getting cut off
+use core::{Error, Handle, PacketHeader};
+use pcap_sys as ffi;
+
+/// Runs capture batches on a handle it owns and reopens the handle with a
+/// larger snaplen when too many packets were truncated, for tools that
+/// can't know the right snaplen in advance.
+///
+/// Truncation is judged over windows of `window` packets (1000 by default):
+/// when at least `threshold` of them (1% by default) were cut off, the
+/// snaplen grows to the longest truncated packet or double the current
+/// one, whichever is larger, but never beyond `max_snaplen`. The handle is
+/// reopened through `Handle::reconfigure` between batches, so only live
+/// captures can be widened, and packets arriving during the reopen are
+/// missed.
+pub struct AutoSnaplen {
+    handle: Option<Handle>,
+    max_snaplen: u32,
+    window: u32,
+    threshold: f64,
+    seen: u32,
+    truncated: u32,
+    longest: u32,
+}
+
+impl AutoSnaplen {
+    pub fn new(handle: Handle, max_snaplen: u32) -> AutoSnaplen {
+        AutoSnaplen {
+            handle: Some(handle),
+            max_snaplen,
+            window: 1000,
+            threshold: 0.01,
+            seen: 0,
+            truncated: 0,
+            longest: 0,
+        }
+    }
+
+    /// Number of packets to judge truncation over
+    pub fn window(mut self, packets: u32) -> AutoSnaplen {
+        self.window = packets.max(1);
+        self
+    }
+
+    /// Fraction of a window that must be truncated to widen the snaplen
+    pub fn threshold(mut self, fraction: f64) -> AutoSnaplen {
+        self.threshold = fraction;
+        self
+    }
+
+    /// The current handle, or `None` if reopening it failed
+    pub fn handle(&self) -> Option<&Handle> {
+        self.handle.as_ref()
+    }
+
+    pub fn into_handle(self) -> Option<Handle> {
+        self.handle
+    }
+
+    /// `Handle::dispatch`, then widen the snaplen if a window has filled up
+    /// with too many truncated packets. Once a reopen has failed, every call
+    /// returns an error.
+    pub fn dispatch<F: FnMut(PacketHeader, &[u8])>(&mut self, count: i32, mut f: F) -> Result<usize, Error> {
+        let handle = match &self.handle {
+            Some(handle) => handle,
+            None => return Err(Error::with_message(ffi::PCAP_ERROR, "the capture was lost reopening it")),
+        };
+        let (seen, truncated, longest) = (&mut self.seen, &mut self.truncated, &mut self.longest);
+        let processed = handle.dispatch(count, |header, data| {
+            *seen += 1;
+            if header.caplen < header.len {
+                *truncated += 1;
+                *longest = (*longest).max(header.len);
+            }
+            f(header, data)
+        })?;
+        if self.seen >= self.window {
+            let snaplen = handle.snapshot()? as u32;
+            if let Some(widened) = self.widened(snaplen) {
+                warn!("{} of {} packets truncated, widening the snaplen from {} to {}", self.truncated, self.seen, snaplen, widened);
+                let handle = self.handle.take().unwrap();
+                self.handle = Some(handle.reconfigure(|config| config.snaplen = widened as i32)?);
+            }
+            self.seen = 0;
+            self.truncated = 0;
+            self.longest = 0;
+        }
+        Ok(processed)
+    }
+
+    /// The snaplen to reopen with after the current window, if any
+    fn widened(&self, snaplen: u32) -> Option<u32> {
+        let too_many = self.truncated as f64 >= self.threshold * self.seen as f64;
+        if self.truncated == 0 || !too_many || snaplen >= self.max_snaplen {
+            return None;
+        }
+        Some(self.longest.max(snaplen.saturating_mul(2)).min(self.max_snaplen))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(seen: u32, truncated: u32, longest: u32) -> AutoSnaplen {
+        AutoSnaplen {
+            handle: None,
+            max_snaplen: 9000,
+            window: 100,
+            threshold: 0.05,
+            seen,
+            truncated,
+            longest,
+        }
+    }
+
+    #[test]
+    fn widens_only_on_sustained_truncation() {
+        assert_eq!(monitor(100, 4, 1500).widened(96), None);
+        assert_eq!(monitor(100, 5, 1500).widened(96), Some(1500));
+        assert_eq!(monitor(100, 50, 150).widened(96), Some(192));
+        assert_eq!(monitor(100, 50, 1500).widened(8000), Some(9000));
+        assert_eq!(monitor(100, 50, 1500).widened(9000), None);
+        assert_eq!(monitor(100, 0, 0).widened(96), None);
+    }
+
+    #[test]
+    fn reports_a_lost_capture() {
+        let mut monitor = monitor(0, 0, 0);
+        assert!(monitor.dispatch(1, |_, _| ()).is_err());
+    }
+}
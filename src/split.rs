@@ -0,0 +1,76 @@
+//! Fanning one capture out into several savefiles, one per filter
+use core::{BpfProgram, Dumper, Error, Handle};
+
+/// Writes each packet of a capture to every output whose filter accepts it,
+/// e.g. one file of DNS and one of HTTP out of a large capture. A packet
+/// matching several filters goes to all of their outputs; one matching none
+/// is dropped.
+///
+/// The filters run in user space through `BpfProgram::matches`, so the
+/// capture is read only once however many outputs there are.
+pub struct Splitter {
+    rules: Vec<(BpfProgram, Dumper)>,
+}
+
+impl Splitter {
+    pub fn new(rules: Vec<(BpfProgram, Dumper)>) -> Splitter {
+        Splitter { rules }
+    }
+
+    /// Read `handle` to the end, usually a savefile opened with
+    /// `open_offline`, and return how many packets went to each output, in
+    /// the order the rules were given
+    pub fn run(&mut self, handle: &Handle) -> Result<Vec<u64>, Error> {
+        let mut written = vec![0; self.rules.len()];
+        let rules = &mut self.rules;
+        handle.loop_(0, |header, data| {
+            for ((filter, dumper), written) in rules.iter_mut().zip(written.iter_mut()) {
+                if filter.matches(&header, data) {
+                    dumper.write(&header, data);
+                    *written += 1;
+                }
+            }
+        })?;
+        Ok(written)
+    }
+
+    /// Flush every output and close them
+    pub fn finish(self) -> Result<(), Error> {
+        for (_, mut dumper) in self.rules {
+            dumper.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{open_dead, open_offline, DataLinkType, PacketHeader, TimeStamp};
+    use testutil::TempFile;
+
+    #[test]
+    #[ignore = "needs a real libpcap to compile filters and read the savefile"]
+    fn packets_go_to_every_matching_output() {
+        let raw = DataLinkType::Raw.as_raw();
+        // raw IPv4 headers, UDP then TCP
+        let packets = [17u8, 6].map(|protocol| {
+            let mut ip = [0u8; 20];
+            ip[0] = 0x45;
+            ip[3] = 20;
+            ip[9] = protocol;
+            (PacketHeader { ts: TimeStamp { sec: 0, usec: 0 }, caplen: 20, len: 20 }, ip)
+        });
+        let input = TempFile::pcap("split-in.pcap", raw, packets);
+        let (udp, ip) = (TempFile::new("split-udp.pcap"), TempFile::new("split-ip.pcap"));
+
+        let dead = open_dead(raw, 65535).unwrap();
+        let handle = open_offline(input.path()).unwrap();
+        let rule = |filter: &str, output: &TempFile| {
+            (dead.compile(filter, true, 0).unwrap(), handle.dump_open(output.path()).unwrap())
+        };
+        let mut splitter = Splitter::new(vec![rule("udp", &udp), rule("ip", &ip)]);
+        assert_eq!(splitter.run(&handle).unwrap(), [1, 2]);
+        splitter.finish().unwrap();
+    }
+}
@@ -0,0 +1,252 @@
+//! Helpers for turning the cumulative `pcap_stats` counters into rates
+use core::{Error, Handle, PacketHeader, Stats, TstampPrecision};
+use std::time::{Duration, Instant};
+
+/// Counter changes between two consecutive `StatsTracker::poll` calls
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct StatsDelta {
+    pub received: u32,
+    pub dropped: u32,
+    pub if_dropped: u32,
+    /// time elapsed since the previous poll
+    pub interval: Duration,
+}
+
+/// Remembers the last `Stats` read from a handle so that each `poll` reports
+/// only what happened since the previous one.
+///
+/// libpcap keeps its counters in 32-bit unsigned integers, so a busy capture
+/// will wrap them. Deltas are computed with wrapping subtraction, which gives
+/// the right answer as long as a counter wraps at most once between polls.
+#[derive(Debug)]
+pub struct StatsTracker {
+    last: Stats,
+    last_polled: Instant,
+}
+
+impl StatsTracker {
+    /// Start tracking from the handle's current counters
+    pub fn new(handle: &Handle) -> Result<StatsTracker, Error> {
+        Ok(StatsTracker::starting_at(handle.stats()?, Instant::now()))
+    }
+
+    fn starting_at(stats: Stats, now: Instant) -> StatsTracker {
+        StatsTracker {
+            last: stats,
+            last_polled: now,
+        }
+    }
+
+    /// Read the handle's counters and return the change since the last poll
+    pub fn poll(&mut self, handle: &Handle) -> Result<StatsDelta, Error> {
+        let stats = handle.stats()?;
+        Ok(self.update(stats, Instant::now()))
+    }
+
+    /// The counters as of the most recent poll
+    pub fn last(&self) -> Stats {
+        self.last
+    }
+
+    fn update(&mut self, stats: Stats, now: Instant) -> StatsDelta {
+        let delta = StatsDelta {
+            received: stats.received.wrapping_sub(self.last.received),
+            dropped: stats.dropped.wrapping_sub(self.last.dropped),
+            if_dropped: stats.if_dropped.wrapping_sub(self.last.if_dropped),
+            interval: now.duration_since(self.last_polled),
+        };
+        self.last = stats;
+        self.last_polled = now;
+        delta
+    }
+}
+
+/// Runs capture batches and reports packet loss as soon as a batch ends.
+///
+/// After every `dispatch` or `loop_` the handle's drop counters are compared
+/// with the previous batch and `on_drop` is called with the number of newly
+/// dropped packets (buffer and interface drops combined) if it increased.
+/// Only works on live captures, since `pcap_stats` fails on savefiles.
+pub struct DropMonitor<D> {
+    tracker: StatsTracker,
+    on_drop: D,
+}
+
+impl<D: FnMut(u32)> DropMonitor<D> {
+    pub fn new(handle: &Handle, on_drop: D) -> Result<DropMonitor<D>, Error> {
+        Ok(DropMonitor {
+            tracker: StatsTracker::new(handle)?,
+            on_drop,
+        })
+    }
+
+    /// `Handle::dispatch` followed by a drop check
+    pub fn dispatch<F: FnMut(PacketHeader, &[u8])>(
+        &mut self,
+        handle: &Handle,
+        count: i32,
+        f: F,
+    ) -> Result<usize, Error> {
+        let processed = handle.dispatch(count, f)?;
+        self.check(handle)?;
+        Ok(processed)
+    }
+
+    /// `Handle::loop_` followed by a drop check
+    pub fn loop_<F: FnMut(PacketHeader, &[u8])>(
+        &mut self,
+        handle: &Handle,
+        count: i32,
+        f: F,
+    ) -> Result<(), Error> {
+        handle.loop_(count, f)?;
+        self.check(handle)?;
+        Ok(())
+    }
+
+    /// Compare the counters now, calling `on_drop` if packets were lost
+    pub fn check(&mut self, handle: &Handle) -> Result<StatsDelta, Error> {
+        let delta = self.tracker.poll(handle)?;
+        let dropped = delta.dropped.saturating_add(delta.if_dropped);
+        if dropped > 0 {
+            (self.on_drop)(dropped);
+        }
+        Ok(delta)
+    }
+}
+
+/// Packet ages (see `Packet::age`) seen during one `LatencyMonitor` interval
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub struct LatencyReport {
+    pub packets: u64,
+    pub max: Duration,
+    pub mean: Duration,
+    /// time covered by the report
+    pub interval: Duration,
+}
+
+/// Runs capture batches and reports how old packets are by the time the
+/// callback sees them, to show how much latency buffering adds.
+///
+/// Every packet's age is measured against the system clock just before the
+/// callback runs. Once `interval` has passed, `on_report` is called with the
+/// maximum and mean since the previous report. Reports are only made when a
+/// packet arrives, so an idle capture produces none.
+pub struct LatencyMonitor<R> {
+    interval: Duration,
+    on_report: R,
+    started: Instant,
+    packets: u64,
+    max: Duration,
+    total: Duration,
+}
+
+impl<R: FnMut(LatencyReport)> LatencyMonitor<R> {
+    pub fn new(interval: Duration, on_report: R) -> LatencyMonitor<R> {
+        LatencyMonitor {
+            interval,
+            on_report,
+            started: Instant::now(),
+            packets: 0,
+            max: Duration::from_secs(0),
+            total: Duration::from_secs(0),
+        }
+    }
+
+    /// `Handle::dispatch`, measuring the age of each packet
+    pub fn dispatch<F: FnMut(PacketHeader, &[u8])>(
+        &mut self,
+        handle: &Handle,
+        count: i32,
+        mut f: F,
+    ) -> Result<usize, Error> {
+        let precision = handle.tstamp_precision();
+        handle.dispatch(count, |header, packet| {
+            self.record(header.ts.elapsed_with_precision(precision), Instant::now());
+            f(header, packet);
+        })
+    }
+
+    /// `Handle::loop_`, measuring the age of each packet
+    pub fn loop_<F: FnMut(PacketHeader, &[u8])>(
+        &mut self,
+        handle: &Handle,
+        count: i32,
+        mut f: F,
+    ) -> Result<(), Error> {
+        let precision = handle.tstamp_precision();
+        handle.loop_(count, |header, packet| {
+            self.record(header.ts.elapsed_with_precision(precision), Instant::now());
+            f(header, packet);
+        })
+    }
+
+    /// Record the age of a packet from a capture with the given precision,
+    /// for captures driven some other way, e.g. by `next_packet`
+    pub fn observe(&mut self, header: &PacketHeader, precision: TstampPrecision) {
+        self.record(header.ts.elapsed_with_precision(precision), Instant::now());
+    }
+
+    fn record(&mut self, age: Duration, now: Instant) {
+        self.packets += 1;
+        self.max = self.max.max(age);
+        self.total += age;
+        let elapsed = now.duration_since(self.started);
+        if elapsed >= self.interval {
+            (self.on_report)(LatencyReport {
+                packets: self.packets,
+                max: self.max,
+                mean: self.total / self.packets as u32,
+                interval: elapsed,
+            });
+            self.started = now;
+            self.packets = 0;
+            self.max = Duration::from_secs(0);
+            self.total = Duration::from_secs(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deltas_survive_counter_wraparound() {
+        let start = Instant::now();
+        let mut tracker = StatsTracker::starting_at(
+            Stats { received: u32::MAX - 9, dropped: 5, if_dropped: 0 },
+            start,
+        );
+
+        let later = start + Duration::from_secs(1);
+        let delta = tracker.update(Stats { received: 10, dropped: 7, if_dropped: 0 }, later);
+
+        assert_eq!(delta, StatsDelta {
+            received: 20,
+            dropped: 2,
+            if_dropped: 0,
+            interval: Duration::from_secs(1),
+        });
+        assert_eq!(tracker.last().received, 10);
+    }
+
+    #[test]
+    fn latency_reports_once_per_interval() {
+        let mut reports = Vec::new();
+        {
+            let mut monitor = LatencyMonitor::new(Duration::from_secs(1), |report| reports.push(report));
+            let start = monitor.started;
+            monitor.record(Duration::from_millis(10), start + Duration::from_millis(100));
+            monitor.record(Duration::from_millis(30), start + Duration::from_millis(500));
+            monitor.record(Duration::from_millis(20), start + Duration::from_secs(1));
+            monitor.record(Duration::from_millis(5), start + Duration::from_millis(1500));
+        }
+        assert_eq!(reports, vec![LatencyReport {
+            packets: 3,
+            max: Duration::from_millis(30),
+            mean: Duration::from_millis(20),
+            interval: Duration::from_secs(1),
+        }]);
+    }
+}
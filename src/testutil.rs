@@ -0,0 +1,44 @@
+//! Fixtures shared by the unit tests
+use savefile::PcapWriter;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use types::PacketHeader;
+
+/// A file in the temporary directory, named after the test and the process
+/// so parallel runs don't collide, and removed when dropped
+pub(crate) struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    pub(crate) fn new(name: &str) -> TempFile {
+        TempFile {
+            path: std::env::temp_dir().join(format!("rustcap-{}-{}", std::process::id(), name)),
+        }
+    }
+
+    /// A savefile holding `packets`, with link type `linktype` and a
+    /// snaplen of 65535
+    pub(crate) fn pcap<I, D>(name: &str, linktype: i32, packets: I) -> TempFile
+    where
+        I: IntoIterator<Item = (PacketHeader, D)>,
+        D: AsRef<[u8]>,
+    {
+        let file = TempFile::new(name);
+        let mut writer = PcapWriter::new(File::create(&file.path).unwrap(), linktype, 65535).unwrap();
+        for (header, data) in packets {
+            writer.write(&header, data.as_ref()).unwrap();
+        }
+        file
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,656 @@
+//! Plain data types shared by the libpcap wrapper and the pure-Rust
+//! savefile codec. Nothing in here calls into libpcap, so these are
+//! available with the `libpcap` feature disabled.
+use std::collections::hash_map::DefaultHasher;
+use filter::Filter;
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::SystemTimeError;
+
+#[derive(Clone,Debug,Eq,PartialEq)]
+pub struct Address {
+    pub address: Option<SocketAddr>,
+    pub netmask: Option<SocketAddr>,
+    pub broadcast: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+}
+
+/// Length of the run of leading one bits in a netmask
+fn prefix_len(netmask: IpAddr) -> u8 {
+    match netmask {
+        IpAddr::V4(mask) => u32::from(mask).leading_ones() as u8,
+        IpAddr::V6(mask) => u128::from(mask).leading_ones() as u8,
+    }
+}
+
+impl Address {
+    /// An address as `NetworkInterface::addresses` lists them, e.g. for
+    /// testing code that consumes interfaces without going through libpcap
+    pub fn new(
+        address: Option<SocketAddr>,
+        netmask: Option<SocketAddr>,
+        broadcast: Option<SocketAddr>,
+        destination: Option<SocketAddr>,
+    ) -> Address {
+        Address { address, netmask, broadcast, destination }
+    }
+}
+
+/// Filter expressions matching an interface address, for use with
+/// `filter::Filter::raw` or `Handle::compile`
+impl Address {
+    /// `host <address>`
+    pub fn as_host_filter(&self) -> Option<String> {
+        let address = self.address?;
+        Some(Filter::new().host(address.ip()).build())
+    }
+
+    /// `net <network>/<prefix length>`, the subnet the address is on. Needs
+    /// a netmask of the same address family.
+    pub fn as_net_filter(&self) -> Option<String> {
+        let address = self.address?.ip();
+        let netmask = self.netmask?.ip();
+        if address.is_ipv4() != netmask.is_ipv4() {
+            return None;
+        }
+        Some(Filter::new().net(address, prefix_len(netmask)).build())
+    }
+
+    /// `port <port>`, if the address has a port; interface addresses
+    /// usually don't
+    pub fn as_port_filter(&self) -> Option<String> {
+        match self.address?.port() {
+            0 => None,
+            port => Some(Filter::new().port(port).build()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub(crate) message: Option<String>,
+    pub(crate) code: i32,
+}
+
+/// Classification of the libpcap `PCAP_ERROR_*` codes
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum ErrorKind {
+    /// `PCAP_ERROR`, the catch-all; the message carries the details
+    Generic,
+    /// `PCAP_ERROR_BREAK`, the loop was stopped by `break_loop`
+    Break,
+    NotActivated,
+    AlreadyActivated,
+    NoSuchDevice,
+    RfmonNotSupported,
+    NotRfmon,
+    PermDenied,
+    IfaceNotUp,
+    CantSetTstampType,
+    PromiscPermDenied,
+    TstampPrecisionNotSupported,
+    /// a capture file in a format the linked libpcap can't read; detected
+    /// by this crate rather than reported by libpcap
+    UnsupportedFormat,
+    /// a capture file that ends in the middle of a header or record, e.g.
+    /// because the program writing it was killed; detected by the pure-Rust
+    /// readers
+    TruncatedFile,
+    /// a code this crate doesn't know about
+    Other(i32),
+}
+
+// Codes for errors this crate detects itself, clear of libpcap's
+// PCAP_ERROR_* values and of its positive warning codes
+pub(crate) const ERROR_UNSUPPORTED_FORMAT: i32 = -1001;
+pub(crate) const ERROR_TRUNCATED_FILE: i32 = -1002;
+
+impl ErrorKind {
+    /// Codes are the `PCAP_ERROR_*` values from pcap.h
+    pub(crate) fn from_code(code: i32) -> ErrorKind {
+        match code {
+            -1 => ErrorKind::Generic,
+            -2 => ErrorKind::Break,
+            -3 => ErrorKind::NotActivated,
+            -4 => ErrorKind::AlreadyActivated,
+            -5 => ErrorKind::NoSuchDevice,
+            -6 => ErrorKind::RfmonNotSupported,
+            -7 => ErrorKind::NotRfmon,
+            -8 => ErrorKind::PermDenied,
+            -9 => ErrorKind::IfaceNotUp,
+            -10 => ErrorKind::CantSetTstampType,
+            -11 => ErrorKind::PromiscPermDenied,
+            -12 => ErrorKind::TstampPrecisionNotSupported,
+            ERROR_UNSUPPORTED_FORMAT => ErrorKind::UnsupportedFormat,
+            ERROR_TRUNCATED_FILE => ErrorKind::TruncatedFile,
+            other => ErrorKind::Other(other),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pcap error {}", self.code)?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::with_message(-1, &err.to_string())
+    }
+}
+
+/// Keeps the `Error` as the source, so its code and message survive
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        let kind = match err.kind() {
+            ErrorKind::PermDenied | ErrorKind::PromiscPermDenied => io::ErrorKind::PermissionDenied,
+            ErrorKind::NoSuchDevice => io::ErrorKind::NotFound,
+            ErrorKind::Break => io::ErrorKind::Interrupted,
+            ErrorKind::RfmonNotSupported
+            | ErrorKind::CantSetTstampType
+            | ErrorKind::TstampPrecisionNotSupported => io::ErrorKind::Unsupported,
+            ErrorKind::UnsupportedFormat => io::ErrorKind::InvalidData,
+            ErrorKind::TruncatedFile => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        ErrorKind::from_code(self.code)
+    }
+
+    pub fn code(&self) -> i32 {
+        self.code
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_ref().map(AsRef::as_ref)
+    }
+
+    /// Whether the condition may clear up by itself, i.e. an interface that
+    /// is not up yet. Everything else (missing device, permissions, bad
+    /// configuration) is permanent. libpcap's generic `PCAP_ERROR` is used
+    /// for failed filters and I/O errors as well as for a failed read from a
+    /// device that went away, so it can't be told apart here;
+    /// `retry::resilient_loop` treats it as transient only when the capture
+    /// loop itself fails.
+    pub fn is_transient(&self) -> bool {
+        self.kind() == ErrorKind::IfaceNotUp
+    }
+
+    pub(crate) fn with_message(code: i32, message: &str) -> Error {
+        Error{ message: Some(message.to_owned()), code }
+    }
+}
+
+/// Ordered by `sec`, then `usec`; only meaningful between timestamps of the
+/// same precision.
+#[derive(Clone,Debug,Eq,PartialEq,Ord,PartialOrd)]
+pub struct TimeStamp {
+    pub sec: i64,
+    pub usec: i64,
+}
+
+/// Resolution of the `usec` field of a `TimeStamp`.
+///
+/// With `Nano` precision libpcap stores nanoseconds in the field that is
+/// otherwise microseconds.
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum TstampPrecision {
+    Micro,
+    Nano,
+}
+
+impl TimeStamp {
+    /// Convert `time` with `usec` holding micro- or nanoseconds as given by
+    /// `precision`. Times before the Unix epoch are rejected.
+    pub fn from_system_time(time: SystemTime, precision: TstampPrecision) -> Result<TimeStamp, SystemTimeError> {
+        let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH)?;
+        Ok(TimeStamp {
+            sec: since_epoch.as_secs() as i64,
+            usec: match precision {
+                TstampPrecision::Micro => since_epoch.subsec_micros() as i64,
+                TstampPrecision::Nano => since_epoch.subsec_nanos() as i64,
+            },
+        })
+    }
+
+    /// The time from `earlier` to `self`, with `usec` in microseconds as in
+    /// the conversion into `SystemTime`. `None` if `earlier` is later.
+    pub fn duration_since(&self, earlier: &TimeStamp) -> Option<Duration> {
+        self.duration_since_with_precision(earlier, TstampPrecision::Micro)
+    }
+
+    /// `duration_since`, with `usec` holding micro- or nanoseconds as given
+    /// by `precision`
+    pub fn duration_since_with_precision(
+        &self,
+        earlier: &TimeStamp,
+        precision: TstampPrecision,
+    ) -> Option<Duration> {
+        let nanos_per_unit = match precision {
+            TstampPrecision::Micro => 1_000,
+            TstampPrecision::Nano => 1,
+        };
+        let nanos = |ts: &TimeStamp| i128::from(ts.sec) * 1_000_000_000 + i128::from(ts.usec) * nanos_per_unit;
+        let delta = nanos(self) - nanos(earlier);
+        if delta < 0 {
+            return None;
+        }
+        Some(Duration::new((delta / 1_000_000_000) as u64, (delta % 1_000_000_000) as u32))
+    }
+
+    /// Time from `self` to now by the system clock, with `usec` in
+    /// microseconds. Zero if `self` is in the future, e.g. because the
+    /// clock was stepped back.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed_with_precision(TstampPrecision::Micro)
+    }
+
+    /// `elapsed`, with `usec` holding micro- or nanoseconds as given by
+    /// `precision`
+    pub fn elapsed_with_precision(&self, precision: TstampPrecision) -> Duration {
+        TimeStamp::from_system_time(SystemTime::now(), precision)
+            .ok()
+            .and_then(|now| now.duration_since_with_precision(self, precision))
+            .unwrap_or_default()
+    }
+}
+
+/// Microsecond precision, matching the conversion into `SystemTime`
+impl TryFrom<SystemTime> for TimeStamp {
+    type Error = SystemTimeError;
+
+    fn try_from(time: SystemTime) -> Result<TimeStamp, SystemTimeError> {
+        TimeStamp::from_system_time(time, TstampPrecision::Micro)
+    }
+}
+
+impl From<TimeStamp> for SystemTime {
+    fn from(ts: TimeStamp) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::new(
+            ts.sec as u64,
+            (ts.usec * 1000) as u32
+        )
+    }
+}
+
+/// The largest snaplen libpcap supports (its `MAXIMUM_SNAPLEN`)
+pub const MAX_SNAPLEN: u32 = 262_144;
+
+#[derive(Clone,Debug)]
+pub struct PacketHeader {
+    pub ts: TimeStamp,
+    pub caplen: u32,
+    pub len: u32,
+}
+
+/// A captured packet with its own copy of the data; `savefile::PcapReader`
+/// items convert into this
+#[derive(Clone,Debug)]
+pub struct Packet {
+    pub header: PacketHeader,
+    pub data: Vec<u8>,
+}
+
+impl Packet {
+    pub fn new(header: PacketHeader, data: Vec<u8>) -> Packet {
+        Packet { header, data }
+    }
+
+    /// Same original length and captured bytes, whenever each was captured;
+    /// for spotting one packet seen on several taps
+    pub fn content_eq(&self, other: &Packet) -> bool {
+        self.header.len == other.header.len && self.data == other.data
+    }
+
+    /// Hash of what `content_eq` compares, for deduplicating through a set.
+    /// Only stable within one process.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.header.len.hash(&mut hasher);
+        self.data.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// How long ago the packet was captured, by the system clock; see
+    /// `TimeStamp::elapsed`. Use `age_with_precision` for packets from a
+    /// nanosecond-precision handle.
+    pub fn age(&self) -> Duration {
+        self.header.ts.elapsed()
+    }
+
+    pub fn age_with_precision(&self, precision: TstampPrecision) -> Duration {
+        self.header.ts.elapsed_with_precision(precision)
+    }
+
+    /// The captured bytes as a `hex_dump`, for logging
+    pub fn hex_dump(&self) -> String {
+        hex_dump(&self.data)
+    }
+}
+
+/// `data` rendered like `tcpdump -X`: one line per 16 bytes, each with the
+/// offset, the bytes in hex in groups of two, and the printable ones as ASCII
+///
+/// ```text
+/// 0x0000:  4500 001c 0000 0000 4011 0000 0a00 0001  E.......@.......
+/// 0x0010:  0a00 0002 03e8 07d0 0008 0000            ............
+/// ```
+pub fn hex_dump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in data.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (i, byte) in chunk.iter().enumerate() {
+            if i > 0 && i % 2 == 0 {
+                hex.push(' ');
+            }
+            hex.push_str(&format!("{:02x}", byte));
+        }
+        let ascii: String = chunk.iter()
+            .map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { '.' })
+            .collect();
+        out.push_str(&format!("0x{:04x}:  {:<39}  {}\n", line * 16, hex, ascii));
+    }
+    out
+}
+
+impl From<(PacketHeader, Vec<u8>)> for Packet {
+    fn from((header, data): (PacketHeader, Vec<u8>)) -> Packet {
+        Packet::new(header, data)
+    }
+}
+
+/// Cumulative capture statistics as reported by `pcap_stats`
+///
+/// The counters are 32 bits wide in libpcap and will wrap on busy links; use
+/// `stats::StatsTracker` to compute per-interval deltas.
+#[derive(Clone,Copy,Debug,Default,Eq,PartialEq)]
+pub struct Stats {
+    /// packets received by the capture
+    pub received: u32,
+    /// packets dropped because there was no room in the buffer
+    pub dropped: u32,
+    /// packets dropped by the network interface or its driver
+    pub if_dropped: u32,
+}
+
+impl Stats {
+    /// `dropped` as a fraction of `received`, 0 when nothing was received.
+    /// Meaningful for the deltas from `stats::StatsTracker` as well as for
+    /// cumulative counters.
+    pub fn drop_rate(&self) -> f64 {
+        if self.received == 0 {
+            0.0
+        } else {
+            self.dropped as f64 / self.received as f64
+        }
+    }
+
+    /// Order by `drop_rate`, e.g. `intervals.iter().max_by(|a, b|
+    /// a.cmp_drop_rate(b))` for the worst interval
+    pub fn cmp_drop_rate(&self, other: &Stats) -> Ordering {
+        self.drop_rate().total_cmp(&other.drop_rate())
+    }
+}
+
+impl std::fmt::Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} received, {} dropped ({:.2}%), {} dropped by the interface",
+            self.received, self.dropped, self.drop_rate() * 100.0, self.if_dropped
+        )
+    }
+}
+
+/// Link-layer header types (the `DLT_*` values) this crate knows by name
+#[derive(Clone,Copy,Debug,Eq,PartialEq,Hash)]
+pub enum DataLinkType {
+    /// BSD loopback, address family in host byte order
+    Null,
+    /// Ethernet
+    En10Mb,
+    Ieee802,
+    Ppp,
+    Fddi,
+    /// raw IPv4 or IPv6, no link-layer header
+    Raw,
+    Ieee802_11,
+    /// OpenBSD loopback, address family in network byte order
+    Loop,
+    /// Linux "cooked" capture, used by the `any` device
+    LinuxSll,
+    Ieee802_11Radio,
+    Ipv4,
+    Ipv6,
+    /// Linux "cooked" capture v2
+    LinuxSll2,
+    Unknown(i32),
+}
+
+#[cfg(not(target_os = "openbsd"))]
+const DLT_RAW: i32 = 12;
+#[cfg(target_os = "openbsd")]
+const DLT_RAW: i32 = 14;
+
+impl DataLinkType {
+    pub fn from_raw(dlt: i32) -> DataLinkType {
+        match dlt {
+            0 => DataLinkType::Null,
+            1 => DataLinkType::En10Mb,
+            6 => DataLinkType::Ieee802,
+            9 => DataLinkType::Ppp,
+            10 => DataLinkType::Fddi,
+            DLT_RAW => DataLinkType::Raw,
+            105 => DataLinkType::Ieee802_11,
+            108 => DataLinkType::Loop,
+            113 => DataLinkType::LinuxSll,
+            127 => DataLinkType::Ieee802_11Radio,
+            228 => DataLinkType::Ipv4,
+            229 => DataLinkType::Ipv6,
+            276 => DataLinkType::LinuxSll2,
+            other => DataLinkType::Unknown(other),
+        }
+    }
+
+    pub fn as_raw(self) -> i32 {
+        match self {
+            DataLinkType::Null => 0,
+            DataLinkType::En10Mb => 1,
+            DataLinkType::Ieee802 => 6,
+            DataLinkType::Ppp => 9,
+            DataLinkType::Fddi => 10,
+            DataLinkType::Raw => DLT_RAW,
+            DataLinkType::Ieee802_11 => 105,
+            DataLinkType::Loop => 108,
+            DataLinkType::LinuxSll => 113,
+            DataLinkType::Ieee802_11Radio => 127,
+            DataLinkType::Ipv4 => 228,
+            DataLinkType::Ipv6 => 229,
+            DataLinkType::LinuxSll2 => 276,
+            DataLinkType::Unknown(other) => other,
+        }
+    }
+
+    pub fn link_header_kind(self) -> LinkHeaderKind {
+        match self {
+            DataLinkType::Raw | DataLinkType::Ipv4 | DataLinkType::Ipv6 => LinkHeaderKind::Fixed(0),
+            DataLinkType::Null | DataLinkType::Loop => LinkHeaderKind::Fixed(4),
+            DataLinkType::Fddi => LinkHeaderKind::Fixed(13),
+            DataLinkType::En10Mb => LinkHeaderKind::Fixed(14),
+            DataLinkType::LinuxSll => LinkHeaderKind::Fixed(16),
+            DataLinkType::LinuxSll2 => LinkHeaderKind::Fixed(20),
+            // source routing, compressible address and control fields, and
+            // radio headers respectively
+            DataLinkType::Ieee802
+            | DataLinkType::Ppp
+            | DataLinkType::Ieee802_11
+            | DataLinkType::Ieee802_11Radio => LinkHeaderKind::Variable,
+            DataLinkType::Unknown(_) => LinkHeaderKind::Unknown,
+        }
+    }
+
+    /// The header length if it is fixed, see `link_header_kind`
+    pub fn link_header_len(self) -> Option<usize> {
+        match self.link_header_kind() {
+            LinkHeaderKind::Fixed(len) => Some(len),
+            _ => None,
+        }
+    }
+
+    /// Linux "cooked" capture, whose pseudo-header replaces the real link
+    /// layer, e.g. on the `any` device
+    pub fn is_cooked(self) -> bool {
+        matches!(self, DataLinkType::LinuxSll | DataLinkType::LinuxSll2)
+    }
+}
+
+/// Whether a link type's header length is known without looking at the packet
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum LinkHeaderKind {
+    /// always this many bytes; tags such as 802.1Q VLAN tags that some
+    /// frames carry after the header aren't counted
+    Fixed(usize),
+    /// depends on the packet, e.g. the radiotap header of 802.11 captures
+    Variable,
+    /// a link type this crate doesn't know
+    Unknown,
+}
+
+impl From<i32> for DataLinkType {
+    fn from(dlt: i32) -> DataLinkType {
+        DataLinkType::from_raw(dlt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_drop_rate() {
+        let stats = |received, dropped| Stats { received, dropped, if_dropped: 1 };
+        assert_eq!(stats(0, 0).drop_rate(), 0.0);
+        assert_eq!(stats(200, 1).drop_rate(), 0.005);
+        assert_eq!(stats(200, 1).to_string(), "200 received, 1 dropped (0.50%), 1 dropped by the interface");
+        let intervals = [stats(100, 1), stats(10, 5), stats(0, 0)];
+        assert_eq!(intervals.iter().max_by(|a, b| a.cmp_drop_rate(b)), Some(&stats(10, 5)));
+    }
+
+    #[test]
+    fn hex_dump_matches_tcpdump_x() {
+        let data = [
+            0x45, 0, 0, 0x1c, 0, 0, 0, 0, 0x40, 0x11, 0, 0, 10, 0, 0, 1,
+            10, 0, 0, 2, 0x03, 0xe8, 0x07, 0xd0, 0, 8, 0, 0, b'h', b'i', b'!',
+        ];
+        assert_eq!(hex_dump(&data), concat!(
+            "0x0000:  4500 001c 0000 0000 4011 0000 0a00 0001  E.......@.......\n",
+            "0x0010:  0a00 0002 03e8 07d0 0008 0000 6869 21    ............hi!\n",
+        ));
+        assert_eq!(hex_dump(&[]), "");
+        let header = PacketHeader { ts: TimeStamp { sec: 0, usec: 0 }, caplen: 2, len: 2 };
+        assert_eq!(Packet::new(header, b"ok".to_vec()).hex_dump(), format!("0x0000:  6f6b{:37}ok\n", ""));
+    }
+
+    #[test]
+    fn timestamp_round_trips_through_system_time() {
+        let ts = TimeStamp { sec: 1_500_000_000, usec: 123_456 };
+        let time: SystemTime = ts.clone().into();
+        assert_eq!(TimeStamp::try_from(time).unwrap(), ts);
+
+        let before_epoch = SystemTime::UNIX_EPOCH - Duration::from_secs(1);
+        assert!(TimeStamp::try_from(before_epoch).is_err());
+    }
+
+    #[test]
+    fn timestamps_order_and_subtract() {
+        let first = TimeStamp { sec: 10, usec: 900_000 };
+        let later = TimeStamp { sec: 11, usec: 100_000 };
+        assert!(first < later);
+        assert_eq!(later.duration_since(&first), Some(Duration::from_millis(200)));
+        assert_eq!(first.duration_since(&later), None);
+        assert_eq!(
+            later.duration_since_with_precision(&first, TstampPrecision::Nano),
+            Some(Duration::from_nanos(999_200_000))
+        );
+    }
+
+    #[test]
+    fn converts_into_io_errors() {
+        let err = io::Error::from(Error::with_message(-8, "no access"));
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(err.to_string(), "pcap error -8: no access");
+        assert_eq!(io::Error::from(Error::with_message(-5, "gone")).kind(), io::ErrorKind::NotFound);
+        assert_eq!(io::Error::from(Error::with_message(-1, "oops")).kind(), io::ErrorKind::Other);
+    }
+
+    #[test]
+    fn builds_filters_from_addresses() {
+        let address = Address {
+            address: Some("192.168.1.20:0".parse().unwrap()),
+            netmask: Some("255.255.255.0:0".parse().unwrap()),
+            broadcast: None,
+            destination: None,
+        };
+        assert_eq!(address.as_host_filter().unwrap(), "host 192.168.1.20");
+        assert_eq!(address.as_net_filter().unwrap(), "net 192.168.1.0/24");
+        assert_eq!(address.as_port_filter(), None);
+
+        let address = Address::new(
+            Some("[fe80::1]:53".parse().unwrap()),
+            Some("[ffff:ffff:ffff:ffff::]:0".parse().unwrap()),
+            None,
+            None,
+        );
+        assert_eq!(address.as_host_filter().unwrap(), "host fe80::1");
+        assert_eq!(address.as_net_filter().unwrap(), "net fe80::/64");
+        assert_eq!(address.as_port_filter().unwrap(), "port 53");
+    }
+
+    #[test]
+    fn knows_link_header_lengths() {
+        assert_eq!(DataLinkType::En10Mb.link_header_kind(), LinkHeaderKind::Fixed(14));
+        assert_eq!(DataLinkType::Ieee802_11Radio.link_header_kind(), LinkHeaderKind::Variable);
+        assert_eq!(DataLinkType::Unknown(147).link_header_kind(), LinkHeaderKind::Unknown);
+        assert_eq!(DataLinkType::Raw.link_header_len(), Some(0));
+        assert_eq!(DataLinkType::Ppp.link_header_len(), None);
+    }
+
+    #[test]
+    fn content_comparison_ignores_timestamps() {
+        let header = |sec| PacketHeader { ts: TimeStamp { sec, usec: 0 }, caplen: 3, len: 3 };
+        let a = Packet::new(header(1), vec![1, 2, 3]);
+        let b = Packet::new(header(2), vec![1, 2, 3]);
+        let c = Packet::new(header(1), vec![1, 2, 4]);
+        assert!(a.content_eq(&b));
+        assert_eq!(a.content_hash(), b.content_hash());
+        assert!(!a.content_eq(&c));
+    }
+
+    #[test]
+    fn packet_age_follows_the_system_clock() {
+        let captured = SystemTime::now() - Duration::from_secs(5);
+        let ts = TimeStamp::from_system_time(captured, TstampPrecision::Nano).unwrap();
+        let packet = Packet::new(PacketHeader { ts, caplen: 0, len: 0 }, vec![]);
+        let age = packet.age_with_precision(TstampPrecision::Nano);
+        assert!(age >= Duration::from_secs(5) && age < Duration::from_secs(60));
+
+        let future = TimeStamp { sec: i64::from(u32::MAX), usec: 0 };
+        assert_eq!(future.elapsed(), Duration::from_secs(0));
+    }
+}
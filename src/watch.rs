@@ -0,0 +1,134 @@
+//! Noticing when a capture interface goes away or comes back
+//!
+//! Interfaces are re-enumerated with `find_all_devs` on a background thread,
+//! which works the same on every platform libpcap supports. Changes that
+//! come and go within one polling interval aren't seen.
+use core::find_all_devs;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// A change in an interface's state between two polls
+#[derive(Clone,Copy,Debug,Eq,PartialEq)]
+pub enum InterfaceEvent {
+    /// the interface is listed again after being absent
+    Appeared,
+    /// the interface is no longer listed
+    Disappeared,
+    /// the interface was brought up
+    Up,
+    /// the interface was brought down
+    Down,
+}
+
+/// An interface's state as of one poll
+#[derive(Clone,Copy,Debug,Default,Eq,PartialEq)]
+pub struct InterfaceState {
+    pub present: bool,
+    pub up: bool,
+}
+
+impl InterfaceState {
+    /// Look `name` up in a fresh device list. An enumeration failure counts
+    /// as the interface being absent.
+    pub fn current(name: &str) -> InterfaceState {
+        find_all_devs()
+            .ok()
+            .and_then(|mut devs| devs.find(|dev| dev.name() == name))
+            .map(|dev| InterfaceState { present: true, up: dev.is_up() })
+            .unwrap_or_default()
+    }
+
+    /// The events that take an interface from `self` to `next`
+    fn transitions(self, next: InterfaceState) -> Vec<InterfaceEvent> {
+        let mut events = vec![];
+        if self.up && !next.up {
+            events.push(InterfaceEvent::Down);
+        }
+        if self.present != next.present {
+            events.push(if next.present {
+                InterfaceEvent::Appeared
+            } else {
+                InterfaceEvent::Disappeared
+            });
+        }
+        if !self.up && next.up {
+            events.push(InterfaceEvent::Up);
+        }
+        events
+    }
+}
+
+/// The background thread started by `watch_interface`; dropping this stops
+/// it, waiting for a poll in progress to finish.
+#[derive(Debug)]
+pub struct InterfaceWatcher {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl InterfaceWatcher {
+    /// Same as dropping the watcher, but reads better at the call site
+    pub fn stop(self) {}
+}
+
+impl Drop for InterfaceWatcher {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Poll interface `name` every `interval` and call `callback` from a
+/// background thread with each change. The starting state is taken when this
+/// is called, so an interface that is already down or absent produces no
+/// event until it changes. Going from up to absent reports `Down` and then
+/// `Disappeared`; the reverse reports `Appeared` and then `Up`.
+pub fn watch_interface<F>(name: &str, interval: Duration, mut callback: F) -> InterfaceWatcher
+where
+    F: FnMut(InterfaceEvent) + Send + 'static,
+{
+    let name = name.to_owned();
+    let stop = Arc::new(AtomicBool::new(false));
+    let stopped = stop.clone();
+    let mut state = InterfaceState::current(&name);
+    let thread = thread::spawn(move || loop {
+        let deadline = Instant::now() + interval;
+        while !stopped.load(Ordering::Relaxed) && Instant::now() < deadline {
+            thread::park_timeout(deadline.saturating_duration_since(Instant::now()));
+        }
+        if stopped.load(Ordering::Relaxed) {
+            return;
+        }
+        let next = InterfaceState::current(&name);
+        for event in state.transitions(next) {
+            callback(event);
+        }
+        state = next;
+    });
+    InterfaceWatcher {
+        stop,
+        thread: Some(thread),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_transitions_in_order() {
+        let absent = InterfaceState::default();
+        let down = InterfaceState { present: true, up: false };
+        let up = InterfaceState { present: true, up: true };
+
+        assert_eq!(up.transitions(absent), [InterfaceEvent::Down, InterfaceEvent::Disappeared]);
+        assert_eq!(absent.transitions(up), [InterfaceEvent::Appeared, InterfaceEvent::Up]);
+        assert_eq!(up.transitions(down), [InterfaceEvent::Down]);
+        assert!(down.transitions(down).is_empty());
+    }
+}